@@ -0,0 +1,50 @@
+// 字句解析と構文解析の速度を計るベンチマーク
+// criterionはネットワークなしでは取得できないので，std::time::Instantで計る
+// cargo bench で実行する
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use prac_compiler::parser::parser4_ll1::Parser;
+use prac_compiler::scan::scan3::Lexer;
+
+// 代入文をstatements個並べたプログラムを作る
+// 1文あたり8トークン程度になる
+fn generate_program(statements: usize) -> String {
+    let mut source = String::from("program bench;\nvar x, y : integer;\nbegin\n");
+    for i in 0..statements {
+        source.push_str(&format!("    x := (y + {}) * 2;\n", i % 1000));
+    }
+    source.push_str("    y := x\nend.\n");
+    source
+}
+
+// fを何回か実行し，1回あたりの最短時間を表示する
+fn bench<F: FnMut()>(name: &str, iterations: u32, mut f: F) {
+    let mut best = Duration::MAX;
+    for _ in 0..iterations {
+        let start = Instant::now();
+        f();
+        best = best.min(start.elapsed());
+    }
+    eprintln!("{:<32} {:>12.3?} (best of {})", name, best, iterations);
+}
+
+fn main() {
+    let small = generate_program(1_000);
+    let large = generate_program(6_250);
+    let token_count = Lexer::new(&large).analyze().len();
+
+    bench("lex 1k statements", 20, || {
+        black_box(Lexer::new(black_box(&small)).analyze());
+    });
+    // 以前のoffset()は呼ぶたびに残りの文字を数えていたので，この規模でO(n^2)が目に見えていた
+    bench(&format!("lex {} tokens", token_count), 10, || {
+        black_box(Lexer::new(black_box(&large)).analyze());
+    });
+    // トークンごとの標準出力はデバッグビルドだけなので，ここには含まれない
+    bench("parse 1k statements", 5, || {
+        let mut parser = Parser::new(Lexer::new(black_box(&small)));
+        black_box(parser.parse_program().unwrap());
+    });
+}
@@ -210,7 +210,7 @@ fn first(a: &[&str]) -> HashSet<String> {
     loop {
         let before = first_a.clone();
         // 1 aが空系列ならFIRST(a)に空系列を追加
-        if a == &["ε"] {
+        if a == ["ε"] {
             first_a.insert("ε".to_string());
         }
         // 2 aが終端記号1文字なら，FIRST(a)にその終端記号を追加
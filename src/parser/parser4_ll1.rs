@@ -1,13 +1,26 @@
 use st::{Node, NodeKind};
 use error::SyntaxError;
 
+use crate::scan::interner::Interner;
+use crate::scan::line_index::LineIndex;
 use crate::scan::scan3::{self, Kind, Lexer, Token};
 
 mod st;
 mod error;
 mod first_set;
-
-pub(crate) type SyntaxResult = std::result::Result<Node, SyntaxError>;
+pub mod call_graph;
+pub mod constants;
+pub mod diagnostic;
+pub mod division;
+pub mod dot;
+pub mod forward;
+pub mod frame;
+pub mod lint;
+pub mod string_length;
+pub mod whole_array;
+
+// エラーは情報量が多く大きくなるので，Boxに包んで返す
+pub(crate) type SyntaxResult = std::result::Result<Node, Box<SyntaxError>>;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[allow(dead_code)]
@@ -52,6 +65,18 @@ pub struct Parser<'a> {
     lexer: Lexer<'a>,
     lookahead: Token,
     cur_token: Kind,
+    // まだ対応するendが来ていないbeginの位置 (start, end)
+    open_begins: Vec<(usize, usize)>,
+    // 最後に読んだトークンの終わりの位置
+    last_end: usize,
+    // 欠けている記号を補ってパースを続けるかどうか
+    recover: bool,
+    // 補ったときに記録したエラー
+    errors: Vec<SyntaxError>,
+    // 副プログラム宣言の中を読んでいるかどうか
+    in_procedure: bool,
+    // end の直前の ; を誤りとするかどうか
+    strict_semicolons: bool,
 }
 
 impl<'a> Parser<'a> {
@@ -61,30 +86,151 @@ impl<'a> Parser<'a> {
             lexer,
             lookahead: init_token,
             cur_token: Kind::Program,
+            open_begins: vec![],
+            last_end: 0,
+            recover: false,
+            errors: vec![],
+            in_procedure: false,
+            strict_semicolons: false,
         }
     }
 
+    /// 文の間の ";" や if の "then" が欠けているだけなら，幅0のトークンを補ってパースを続ける
+    /// 補うたびにエラーを記録するので，パースが終わった後に errors() で確認する
+    pub fn with_recovery(mut self, enabled: bool) -> Self {
+        self.recover = enabled;
+        self
+    }
+
+    /// begin x := 1; end のように，end の直前に ; を書くことを誤りとするかどうか
+    /// MPPLでは ; は文の区切りで，; と end の間は空文になるので，既定では受け付ける
+    pub fn with_strict_semicolons(mut self, enabled: bool) -> Self {
+        self.strict_semicolons = enabled;
+        self
+    }
+
+    /// with_interning の字句解析器を渡した場合の，これまでに読んだ名前の表
+    /// parse_program が返す根のノードも，同じ表を Node::interner で持つ
+    pub fn interner(&self) -> Option<&Interner> {
+        self.lexer.interner()
+    }
+
+    /// 回復モードで補ったときに記録したエラー
+    pub fn errors(&self) -> &[SyntaxError] {
+        &self.errors
+    }
+
     fn match_token(&self, kind: scan3::Kind) -> bool {
-        if self.lookahead.kind == kind {
-            true
-        } else {
-            false
-        }
+        self.lookahead.kind == kind
     }
 
     fn match_consume_token(&mut self, kind: scan3::Kind) -> SyntaxResult {
         if self.match_token(kind) {
             self.cur_token = kind;
             let current = self.lookahead.clone();
+            self.last_end = current.end;
             self.lookahead = self.lexer.read_next_token();
+            // トークンごとの出力は遅いので，デバッグビルドでだけ行う
+            #[cfg(debug_assertions)]
             println!("consume token: {:?}, lookahead: {:?}", kind, self.lookahead);
             Ok(Node::new(NodeKind::Token(current), None))
         } else {
+            #[cfg(debug_assertions)]
             println!(
                 "consume token error: {:?}, lookahead: {:?}",
                 kind, self.lookahead
             );
-            Err(error::SyntaxError::new(self, &[kind], &[]))
+            let err = self
+                .nesting_error(kind)
+                .unwrap_or_else(|| error::SyntaxError::new(self, &[kind], &[]));
+            Err(err.into())
+        }
+    }
+
+    // 先読みが空でない文の始まりであるか
+    // 文のFIRST集合には空文の後ろに来る ; end else も含まれるので，空でない文だけを見る
+    fn starts_statement(&self) -> bool {
+        [
+            SyntaxKind::AssignmentStatement,
+            SyntaxKind::ConditionStatement,
+            SyntaxKind::IterationStatement,
+            SyntaxKind::ExitStatement,
+            SyntaxKind::CallStatement,
+            SyntaxKind::ReturnStatement,
+            SyntaxKind::InputStatement,
+            SyntaxKind::OutputStatement,
+            SyntaxKind::CompoundStatement,
+        ]
+        .iter()
+        .any(|s| self.match_syntax_first_token(*s))
+    }
+
+    // 回復モードで，先読みの名前の次が := であるか
+    // 名前は式の始まりにも代入文の始まりにもなるので，2つ先のトークンで見分ける
+    fn next_is_assignment(&self) -> bool {
+        self.recover
+            && self.match_token(Kind::Name)
+            && self.lexer.clone().read_next_token().kind == Kind::Assign
+    }
+
+    /// 回復モードで，次が文の始まりなのにkindがない場合は，直前のトークンの直後に幅0のkindを補う
+    fn synthesize_before_statement(&mut self, kind: Kind, spelling: &str) -> Option<Node> {
+        if !self.recover || self.match_token(kind) || !self.starts_statement() {
+            return None;
+        }
+
+        let at = self.last_end;
+        self.errors.push(error::SyntaxError::with_message(
+            self,
+            &format!("missing '{}'", spelling),
+            &[(at, at)],
+        ));
+        let token = Token {
+            kind,
+            start: at,
+            end: at,
+            value: scan3::TokenValue::None,
+        };
+        Some(Node::new(NodeKind::Token(token), None))
+    }
+
+    /// 宣言の後ろには，beginで始まる複合文しか書けない
+    /// 本体の begin を書き忘れると，後ろの文を宣言の続きとして読んでしまうので，その位置を示すエラーにする
+    fn missing_begin_error(&self, start: usize, end: usize) -> Box<error::SyntaxError> {
+        let body = if self.in_procedure {
+            "procedure"
+        } else {
+            "program"
+        };
+        error::SyntaxError::with_message(
+            self,
+            &format!("{} body must be a 'begin ... end' block", body),
+            &[(start, end)],
+        )
+        .into()
+    }
+
+    /// beginとendの対応が崩れていることが原因と分かるエラーなら，それを返す
+    fn nesting_error(&self, expected: Kind) -> Option<error::SyntaxError> {
+        let lk = self.lookahead.kind;
+        match self.open_begins.last() {
+            // 閉じていないbeginがあるのに，ソースコードが終わった
+            Some(&(start, end))
+                if lk == Kind::Eof || (expected == Kind::End && lk == Kind::Dot) =>
+            {
+                let (line, _) = LineIndex::new(self.lexer.source).line_col(start);
+                Some(error::SyntaxError::with_message(
+                    self,
+                    &format!("unclosed 'begin' started at line {}", line),
+                    &[(start, end)],
+                ))
+            }
+            None if lk == Kind::End => Some(error::SyntaxError::with_message(
+                self,
+                "unexpected 'end' with no matching 'begin'",
+                &[(self.lookahead.start, self.lookahead.end)],
+            )),
+            _ => None,
         }
     }
 
@@ -94,7 +240,7 @@ impl<'a> Parser<'a> {
             return true;
         }
         let lk = self.lookahead.kind;
-        let binding = first_set::FIRST_SETS;
+        let binding = &first_set::FIRST_SETS;
         let tokens = binding.iter().find(|x| x.symbol == syntax).unwrap();
         tokens.first_set.contains(&lk)
     }
@@ -139,7 +285,7 @@ impl<'a> Parser<'a> {
             };
             Ok(node)
         } else {
-            Err(error::SyntaxError::new(self, &[], &[syntax]))
+            Err(error::SyntaxError::new(self, &[], &[syntax]).into())
         }
     }
 
@@ -147,16 +293,44 @@ impl<'a> Parser<'a> {
     /// "program" "名前" ";" ブロック "."
     pub fn parse_program(&mut self) -> SyntaxResult {
         // マクロ構文のprogramに該当
-        Ok(Node::new(
-            NodeKind::Syntax(SyntaxKind::Program),
-            Some(vec![
-                self.match_consume_token(Kind::Program)?,
-                self.match_consume_token(Kind::Name)?,
-                self.match_consume_token(Kind::Semicolon)?,
-                self.match_consume_syntax(SyntaxKind::Block)?,
-                self.match_consume_token(Kind::Dot)?,
-            ]),
-        ))
+        let mut nodes = vec![
+            self.match_consume_token(Kind::Program)?,
+            self.match_consume_token(Kind::Name)?,
+            self.match_consume_token(Kind::Semicolon)?,
+        ];
+        if !self.match_syntax_first_token(SyntaxKind::Block) {
+            return Err(self.missing_begin_error(self.lookahead.start, self.lookahead.end));
+        }
+        nodes.push(self.match_consume_syntax(SyntaxKind::Block)?);
+        // プログラムは最後の end の後ろの . で終わる
+        if !self.match_token(Kind::Dot) && self.nesting_error(Kind::Dot).is_none() {
+            let end = nodes.last().and_then(|n| n.last_token()).unwrap();
+            return Err(error::SyntaxError::with_message(
+                self,
+                "expected '.' after the final 'end' of the program",
+                &[(end.start, end.end)],
+            )
+            .into());
+        }
+        nodes.push(self.match_consume_token(Kind::Dot)?);
+        let mut program = Node::new(NodeKind::Syntax(SyntaxKind::Program), Some(nodes));
+        // 先頭や末尾の注釈・空白も含めて，根のノードがソースコード全体に対応するようにする
+        program.cover_source(self.lexer.source.len());
+        if let Some(interner) = self.lexer.interner() {
+            program.attach_interner(interner.clone());
+        }
+        program.assign_ids();
+        Ok(program)
+    }
+
+    /// プログラム全体ではなく，式1つだけをパースする
+    /// 式の後ろに余分なトークンが続く場合はエラーとする
+    pub fn parse_expression(source: &str) -> SyntaxResult {
+        let mut parser = Parser::new(Lexer::new(source));
+        let mut node = parser.match_consume_syntax(SyntaxKind::Expression)?;
+        parser.match_consume_token(Kind::Eof)?;
+        node.assign_ids();
+        Ok(node)
     }
 
     /// { 変数宣言部 | 副プログラム宣言 } 複合文
@@ -173,6 +347,9 @@ impl<'a> Parser<'a> {
                 _ => break,
             }
         }
+        if !self.match_token(Kind::Begin) {
+            return Err(self.missing_begin_error(self.lookahead.start, self.lookahead.end));
+        }
         nodes.push(self.match_consume_syntax(SyntaxKind::CompoundStatement)?);
         Ok(Node::new(NodeKind::Syntax(SyntaxKind::Block), Some(nodes)))
     }
@@ -183,14 +360,20 @@ impl<'a> Parser<'a> {
             self.match_consume_token(Kind::Var)?,
             self.match_consume_syntax(SyntaxKind::VariableNames)?,
             self.match_consume_token(Kind::Colon)?,
-            self.match_consume_syntax(SyntaxKind::Type)?,
+            self.type_after_colon()?,
             self.match_consume_token(Kind::Semicolon)?,
         ];
         while self.lookahead.kind == Kind::Name {
+            let first = (self.lookahead.start, self.lookahead.end);
+            let names = self.match_consume_syntax(SyntaxKind::VariableNames)?;
+            // x := 1 や a[1] := 1 は宣言ではなく，begin を書き忘れた本体の文
+            if matches!(self.lookahead.kind, Kind::Assign | Kind::LBracket) {
+                return Err(self.missing_begin_error(first.0, first.1));
+            }
             let n = vec![
-                self.match_consume_syntax(SyntaxKind::VariableNames)?,
+                names,
                 self.match_consume_token(Kind::Colon)?,
-                self.match_consume_syntax(SyntaxKind::Type)?,
+                self.type_after_colon()?,
                 self.match_consume_token(Kind::Semicolon)?,
             ];
             nodes.extend(n);
@@ -225,6 +408,21 @@ impl<'a> Parser<'a> {
         ))
     }
 
+    /// 宣言の ":" に続く型
+    /// 出力指定の ":" と取り違えた x : 4 のような書き方には，型が必要なことを示すエラーを返す
+    fn type_after_colon(&mut self) -> SyntaxResult {
+        if !self.match_syntax_first_token(SyntaxKind::Type) {
+            let colon = (self.last_end - 1, self.last_end);
+            return Err(error::SyntaxError::with_message(
+                self,
+                "expected a type after ':'",
+                &[colon, (self.lookahead.start, self.lookahead.end)],
+            )
+            .into());
+        }
+        self.match_consume_syntax(SyntaxKind::Type)
+    }
+
     /// 標準型 | 配列型
     /// 予約語に引っかかるのを防ぐため，アンダーバーをつけている
     fn type_(&mut self) -> SyntaxResult {
@@ -238,7 +436,7 @@ impl<'a> Parser<'a> {
             _ if self.match_syntax_first_token(SyntaxKind::StandardType) => {
                 self.match_consume_syntax(SyntaxKind::StandardType)?
             }
-            _ => return Err(err),
+            _ => return Err(err.into()),
         };
         Ok(Node::new(
             NodeKind::Syntax(SyntaxKind::Type),
@@ -253,7 +451,7 @@ impl<'a> Parser<'a> {
             Kind::Integer => self.match_consume_token(Kind::Integer)?,
             Kind::Boolean => self.match_consume_token(Kind::Boolean)?,
             Kind::Char => self.match_consume_token(Kind::Char)?,
-            _ => return Err(err),
+            _ => return Err(err.into()),
         };
         Ok(Node::new(
             NodeKind::Syntax(SyntaxKind::StandardType),
@@ -277,7 +475,8 @@ impl<'a> Parser<'a> {
         ))
     }
 
-    /// "procedure" 手続き名 [ 仮引数部 ] ";" [ 変数宣言部 ] 複合文 ";"
+    /// "procedure" 手続き名 [ 仮引数部 ] ";" ( "forward" | [ 変数宣言部 ] 複合文 ) ";"
+    /// forwardの場合は本体を持たず，同じ名前の手続きが後で定義される
     fn subprogram_declaration(&mut self) -> SyntaxResult {
         let mut nodes = vec![
             self.match_consume_token(Kind::Procedure)?,
@@ -287,10 +486,14 @@ impl<'a> Parser<'a> {
             nodes.push(self.match_consume_syntax(SyntaxKind::FormalParameters)?);
         }
         nodes.push(self.match_consume_token(Kind::Semicolon)?);
-        if self.match_syntax_first_token(SyntaxKind::VariableDeclaration) {
-            nodes.push(self.match_consume_syntax(SyntaxKind::VariableDeclaration)?);
+        if self.match_token(Kind::Forward) {
+            nodes.push(self.match_consume_token(Kind::Forward)?);
+        } else {
+            self.in_procedure = true;
+            let body = self.procedure_body();
+            self.in_procedure = false;
+            nodes.extend(body?);
         }
-        nodes.push(self.match_consume_syntax(SyntaxKind::CompoundStatement)?);
         nodes.push(self.match_consume_token(Kind::Semicolon)?);
 
         Ok(Node::new(
@@ -299,6 +502,19 @@ impl<'a> Parser<'a> {
         ))
     }
 
+    // [ 変数宣言部 ] 複合文
+    fn procedure_body(&mut self) -> std::result::Result<Vec<Node>, Box<error::SyntaxError>> {
+        let mut nodes = vec![];
+        if self.match_syntax_first_token(SyntaxKind::VariableDeclaration) {
+            nodes.push(self.match_consume_syntax(SyntaxKind::VariableDeclaration)?);
+        }
+        if !self.match_token(Kind::Begin) {
+            return Err(self.missing_begin_error(self.lookahead.start, self.lookahead.end));
+        }
+        nodes.push(self.match_consume_syntax(SyntaxKind::CompoundStatement)?);
+        Ok(nodes)
+    }
+
     /// "名前"
     fn procedure_name(&mut self) -> SyntaxResult {
         let nodes = vec![self.match_consume_token(Kind::Name)?];
@@ -314,14 +530,14 @@ impl<'a> Parser<'a> {
             self.match_consume_token(Kind::LParen)?,
             self.match_consume_syntax(SyntaxKind::VariableNames)?,
             self.match_consume_token(Kind::Colon)?,
-            self.match_consume_syntax(SyntaxKind::Type)?,
+            self.type_after_colon()?,
         ];
         while self.lookahead.kind == Kind::Semicolon {
             let n = vec![
                 self.match_consume_token(Kind::Semicolon)?,
                 self.match_consume_syntax(SyntaxKind::VariableNames)?,
                 self.match_consume_token(Kind::Colon)?,
-                self.match_consume_syntax(SyntaxKind::Type)?,
+                self.type_after_colon()?,
             ];
             nodes.extend(n);
         }
@@ -335,18 +551,36 @@ impl<'a> Parser<'a> {
 
     /// "begin" 文 { ";" 文 } "end"
     fn compound_statement(&mut self) -> SyntaxResult {
-        let mut nodes = vec![
-            self.match_consume_token(Kind::Begin)?,
-            self.match_consume_syntax(SyntaxKind::Statement)?,
-        ];
-        while self.lookahead.kind == Kind::Semicolon {
-            let n = vec![
-                self.match_consume_token(Kind::Semicolon)?,
-                self.match_consume_syntax(SyntaxKind::Statement)?,
-            ];
-            nodes.extend(n);
+        let begin = (self.lookahead.start, self.lookahead.end);
+        let mut nodes = vec![self.match_consume_token(Kind::Begin)?];
+        self.open_begins.push(begin);
+        nodes.push(self.match_consume_syntax(SyntaxKind::Statement)?);
+        loop {
+            let semicolon = match self.synthesize_before_statement(Kind::Semicolon, ";") {
+                Some(n) => n,
+                None if self.lookahead.kind == Kind::Semicolon => {
+                    self.match_consume_token(Kind::Semicolon)?
+                }
+                None => break,
+            };
+            if self.strict_semicolons && self.match_token(Kind::End) {
+                let (start, end) = semicolon.span().unwrap();
+                let err = error::SyntaxError::with_message(
+                    self,
+                    "empty statement not allowed; remove the ';' before 'end'",
+                    &[(start, end)],
+                );
+                // 回復モードでは記録するだけで，空文としてパースを続ける
+                if !self.recover {
+                    return Err(err.into());
+                }
+                self.errors.push(err);
+            }
+            nodes.push(semicolon);
+            nodes.push(self.match_consume_syntax(SyntaxKind::Statement)?);
         }
         nodes.push(self.match_consume_token(Kind::End)?);
+        self.open_begins.pop();
 
         Ok(Node::new(
             NodeKind::Syntax(SyntaxKind::CompoundStatement),
@@ -400,9 +634,13 @@ impl<'a> Parser<'a> {
         let mut nodes = vec![
             self.match_consume_token(Kind::If)?,
             self.match_consume_syntax(SyntaxKind::Expression)?,
-            self.match_consume_token(Kind::Then)?,
-            self.match_consume_syntax(SyntaxKind::Statement)?,
         ];
+        let then = match self.synthesize_before_statement(Kind::Then, "then") {
+            Some(n) => n,
+            None => self.match_consume_token(Kind::Then)?,
+        };
+        nodes.push(then);
+        nodes.push(self.match_consume_syntax(SyntaxKind::Statement)?);
         while self.lookahead.kind == Kind::Else {
             let n = vec![
                 self.match_consume_token(Kind::Else)?,
@@ -482,21 +720,61 @@ impl<'a> Parser<'a> {
 
     /// return
     fn return_statement(&mut self) -> SyntaxResult {
+        let ret = (self.lookahead.start, self.lookahead.end);
         let n = self.match_consume_token(Kind::Return)?;
+        let mut nodes = vec![n];
+        // 手続きは値を返さないので，returnの後ろに式は書けない
+        // 回復する場合，return x := 1 のように後ろが代入文であれば ; の書き忘れとみなして，呼び出し元で ; を補う
+        // 式の始まりにならない文 (if や call など) も同じ
+        if self.match_syntax_first_token(SyntaxKind::Expression) && !self.next_is_assignment() {
+            let value = (self.lookahead.start, self.lookahead.end);
+            let err = error::SyntaxError::with_message(
+                self,
+                "'return' cannot have a value; MPPL procedures do not return values",
+                &[ret, value],
+            );
+            if !self.recover {
+                return Err(err.into());
+            }
+            // 回復する場合は，式を読み飛ばさずに木に残して続ける
+            self.errors.push(err);
+            nodes.push(self.match_consume_syntax(SyntaxKind::Expression)?);
+        }
 
         Ok(Node::new(
             NodeKind::Syntax(SyntaxKind::ReturnStatement),
-            Some(vec![n]),
+            Some(nodes),
         ))
     }
 
     /// 左辺部 ":=" 式
     fn assignment_statement(&mut self) -> SyntaxResult {
+        let left_part = self.match_consume_syntax(SyntaxKind::LeftPart)?;
+        let assign = (self.lookahead.start, self.lookahead.end);
+        // 式だけでは文にならないので，x = 1 は := の書き間違いとみなす
+        if self.match_token(Kind::Equal) {
+            return Err(error::SyntaxError::with_message(
+                self,
+                "did you mean ':=' (assignment) instead of '=' (comparison)?",
+                &[assign],
+            )
+            .into());
+        }
         let nodes = vec![
-            self.match_consume_syntax(SyntaxKind::LeftPart)?,
+            left_part,
             self.match_consume_token(Kind::Assign)?,
             self.match_consume_syntax(SyntaxKind::Expression)?,
         ];
+        // 代入は文であって式ではないので，a := b := c のような連鎖代入はできない
+        if self.match_token(Kind::Assign) {
+            let chained = (self.lookahead.start, self.lookahead.end);
+            return Err(error::SyntaxError::with_message(
+                self,
+                "chained assignment is not allowed in MPPL",
+                &[assign, chained],
+            )
+            .into());
+        }
 
         Ok(Node::new(
             NodeKind::Syntax(SyntaxKind::AssignmentStatement),
@@ -792,11 +1070,18 @@ impl<'a> Parser<'a> {
                 let mut n = vec![self.match_consume_syntax(SyntaxKind::Expression)?];
 
                 if self.lookahead.kind == Kind::Colon {
-                    let n2 = vec![
-                        self.match_consume_token(Kind::Colon)?,
-                        self.match_consume_token(Kind::UnsignedInteger)?,
-                    ];
-                    n.extend(n2);
+                    let colon = (self.lookahead.start, self.lookahead.end);
+                    n.push(self.match_consume_token(Kind::Colon)?);
+                    // 出力指定の ":" の後ろは桁数なので，宣言のように型を書くことはできない
+                    if !self.match_token(Kind::UnsignedInteger) {
+                        return Err(error::SyntaxError::with_message(
+                            self,
+                            "expected integer width after ':'",
+                            &[colon, (self.lookahead.start, self.lookahead.end)],
+                        )
+                        .into());
+                    }
+                    n.push(self.match_consume_token(Kind::UnsignedInteger)?);
                 }
                 nodes.extend(n);
             }
@@ -819,8 +1104,31 @@ impl<'a> Parser<'a> {
 
 #[cfg(test)]
 mod tests {
+    use super::st::{Node, NodeId, NodeKind};
     use super::Parser;
-    use crate::scan::scan3::Lexer;
+    use super::SyntaxKind;
+    use crate::scan::scan3::{Kind, Lexer, TokenValue};
+
+    // 木の形を比較しやすいように，S式風の文字列にする
+    fn sexp(node: &Node) -> String {
+        match &node.kind {
+            NodeKind::Token(t) => match &t.value {
+                TokenValue::Integer(n) => n.to_string(),
+                TokenValue::String(s) => s.clone(),
+                TokenValue::Ident(symbol) => format!("#{}", symbol.0),
+                TokenValue::None => format!("{:?}", t.kind),
+            },
+            NodeKind::Syntax(kind) => {
+                let mut buf = format!("({:?}", kind);
+                for child in node.children.iter().flatten() {
+                    buf.push(' ');
+                    buf.push_str(&sexp(child));
+                }
+                buf.push(')');
+                buf
+            }
+        }
+    }
 
     // ./parse/samples/1.mpl
     // ./parse/answes/1.mpl
@@ -859,4 +1167,413 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_chained_assignment() {
+        let source = "program p; var a, b, c : integer; begin a := b := c end.";
+        let lexer = Lexer::new(source);
+        let mut parser = Parser::new(lexer);
+        let err = parser.parse_program().unwrap_err();
+
+        assert_eq!(
+            err.message.as_deref(),
+            Some("chained assignment is not allowed in MPPL")
+        );
+        let first = source.find(":=").unwrap();
+        let second = source.rfind(":=").unwrap();
+        assert_eq!(err.spans, vec![(first, first + 2), (second, second + 2)]);
+    }
+
+    #[test]
+    fn test_unclosed_begin() {
+        let source = "program p;\nvar x : integer;\nbegin\n  begin\n    x := 1\n  end\n.";
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+
+        // 内側のbeginは閉じているので，3行目の外側のbeginが閉じていない
+        assert_eq!(
+            err.message.as_deref(),
+            Some("unclosed 'begin' started at line 3")
+        );
+        let outer = source.find("begin").unwrap();
+        assert_eq!(err.spans, vec![(outer, outer + 5)]);
+
+        let source = "program p;\nbegin\n  begin\n  end";
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+        assert_eq!(
+            err.message.as_deref(),
+            Some("unclosed 'begin' started at line 2")
+        );
+    }
+
+    #[test]
+    fn test_unexpected_end() {
+        let source = "program p;\nvar x : integer;\nbegin\n  x := 1\nend\nend.";
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+
+        assert_eq!(
+            err.message.as_deref(),
+            Some("unexpected 'end' with no matching 'begin'")
+        );
+        let extra = source.rfind("end").unwrap();
+        assert_eq!(err.spans, vec![(extra, extra + 3)]);
+    }
+
+    #[test]
+    fn test_equal_instead_of_assign() {
+        let source = "program p; var x : integer; a : array[3] of integer;
+            begin x = 1; a[x] = 2 end.";
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+        assert_eq!(
+            err.message.as_deref(),
+            Some("did you mean ':=' (assignment) instead of '=' (comparison)?")
+        );
+        let equal = source.find("= 1").unwrap();
+        assert_eq!(err.spans, vec![(equal, equal + 1)]);
+
+        let source = "program p; var a : array[3] of integer; begin a[1] = 2 end.";
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+        let equal = source.find("= 2").unwrap();
+        assert_eq!(err.spans, vec![(equal, equal + 1)]);
+    }
+
+    #[test]
+    fn test_return_with_value() {
+        let source = "program p; var x : integer;
+            procedure q; begin if x > 0 then return else x := 1 end;
+            begin call q end.";
+        assert!(Parser::new(Lexer::new(source)).parse_program().is_ok());
+
+        let source = "program p; var x : integer;
+            procedure q; begin if x > 0 then return x else return end;
+            begin call q end.";
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+        assert_eq!(
+            err.message.as_deref(),
+            Some("'return' cannot have a value; MPPL procedures do not return values")
+        );
+        let ret = source.find("return").unwrap();
+        let value = source.find("x else").unwrap();
+        assert_eq!(err.spans, vec![(ret, ret + 6), (value, value + 1)]);
+    }
+
+    #[test]
+    fn test_colon_context() {
+        let source = "program p; var x : 4; begin end.";
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+        assert_eq!(err.message.as_deref(), Some("expected a type after ':'"));
+        let colon = source.find(':').unwrap();
+        let four = source.find('4').unwrap();
+        assert_eq!(err.spans, vec![(colon, colon + 1), (four, four + 1)]);
+
+        let source = "program p; var x : integer; begin write(x : integer) end.";
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+        assert_eq!(
+            err.message.as_deref(),
+            Some("expected integer width after ':'")
+        );
+        let colon = source.rfind(':').unwrap();
+        let integer = source.rfind("integer").unwrap();
+        assert_eq!(err.spans, vec![(colon, colon + 1), (integer, integer + 7)]);
+
+        let source = "program p; var x : integer; begin write(x : 4) end.";
+        assert!(Parser::new(Lexer::new(source)).parse_program().is_ok());
+    }
+
+    #[test]
+    fn test_program_body() {
+        let source = "program p; var x : integer; begin x := 1 end.";
+        assert!(Parser::new(Lexer::new(source)).parse_program().is_ok());
+
+        let source = "program p; var x : integer; x := 1 end.";
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+        assert_eq!(
+            err.message.as_deref(),
+            Some("program body must be a 'begin ... end' block")
+        );
+        let x = source.find("x :=").unwrap();
+        assert_eq!(err.spans, vec![(x, x + 1)]);
+
+        let source = "program p; procedure q; var y : integer; y := 1 end; begin end.";
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+        assert_eq!(
+            err.message.as_deref(),
+            Some("procedure body must be a 'begin ... end' block")
+        );
+        let source = "program p; writeln end.";
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+        let writeln = source.find("writeln").unwrap();
+        assert_eq!(err.spans, vec![(writeln, writeln + 7)]);
+
+        let source = "program p; var x : integer; begin x := 1 end";
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+        assert_eq!(
+            err.message.as_deref(),
+            Some("expected '.' after the final 'end' of the program")
+        );
+        let end = source.rfind("end").unwrap();
+        assert_eq!(err.spans, vec![(end, end + 3)]);
+    }
+
+    #[test]
+    fn test_strict_semicolons() {
+        let trailing = "program p; var x : integer; begin x:=1; end.";
+        let separated = "program p; var x, y : integer; begin x:=1; y:=2 end.";
+        for source in [trailing, separated] {
+            assert!(Parser::new(Lexer::new(source)).parse_program().is_ok());
+        }
+
+        let strict = |source| {
+            Parser::new(Lexer::new(source))
+                .with_strict_semicolons(true)
+                .parse_program()
+        };
+        assert!(strict(separated).is_ok());
+        let err = strict(trailing).unwrap_err();
+        assert_eq!(
+            err.message.as_deref(),
+            Some("empty statement not allowed; remove the ';' before 'end'")
+        );
+        let semicolon = trailing.rfind(';').unwrap();
+        assert_eq!(err.spans, vec![(semicolon, semicolon + 1)]);
+    }
+
+    #[test]
+    fn test_recover_missing_semicolon() {
+        let source = "program p; var x, y : integer; begin x := 1 y := 2 end.";
+        let mut parser = Parser::new(Lexer::new(source)).with_recovery(true);
+        let program = parser.parse_program().unwrap();
+
+        let compound = program
+            .descendants()
+            .into_iter()
+            .find(|n| n.is_syntax(SyntaxKind::CompoundStatement))
+            .unwrap();
+        assert_eq!(compound.syntax_children(SyntaxKind::Statement).count(), 2);
+
+        let at = source.find("1 y").unwrap() + 1;
+        assert_eq!(parser.errors().len(), 1);
+        assert_eq!(parser.errors()[0].message.as_deref(), Some("missing ';'"));
+        assert_eq!(parser.errors()[0].spans, vec![(at, at)]);
+        let semicolon = compound.children()[2].first_token().unwrap();
+        assert_eq!(
+            (semicolon.kind, semicolon.start, semicolon.end),
+            (Kind::Semicolon, at, at)
+        );
+
+        // 回復しない場合は，今までどおりエラーになる
+        assert!(Parser::new(Lexer::new(source)).parse_program().is_err());
+    }
+
+    #[test]
+    fn test_recover_missing_semicolon_after_return() {
+        let source = "program p; var x : integer;
+            procedure q; begin return
+                x := 1 end;
+            begin call q end.";
+        let mut parser = Parser::new(Lexer::new(source)).with_recovery(true);
+        let program = parser.parse_program().unwrap();
+
+        let body = program
+            .descendants()
+            .into_iter()
+            .find(|n| n.is_syntax(SyntaxKind::CompoundStatement))
+            .unwrap();
+        assert_eq!(body.syntax_children(SyntaxKind::Statement).count(), 2);
+        let at = source.find("return").unwrap() + "return".len();
+        assert_eq!(parser.errors().len(), 1);
+        assert_eq!(parser.errors()[0].message.as_deref(), Some("missing ';'"));
+        assert_eq!(parser.errors()[0].spans, vec![(at, at)]);
+
+        // 回復しない場合は，値を返そうとしたものとみなす
+        let err = Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+        assert_eq!(
+            err.message.as_deref(),
+            Some("'return' cannot have a value; MPPL procedures do not return values")
+        );
+    }
+
+    #[test]
+    fn test_recover_return_with_value() {
+        let message = "'return' cannot have a value; MPPL procedures do not return values";
+        for source in [
+            "program p; var x : integer;
+            procedure q; begin return x end;
+            begin call q end.",
+            "program p; var x : integer;
+            procedure q; begin if x > 0 then return x else return end;
+            begin call q end.",
+        ] {
+            let mut parser = Parser::new(Lexer::new(source)).with_recovery(true);
+            assert!(parser.parse_program().is_ok(), "{}", source);
+            let messages = parser
+                .errors()
+                .iter()
+                .map(|e| e.message.as_deref())
+                .collect::<Vec<_>>();
+            assert_eq!(messages, vec![Some(message)], "{}", source);
+            let ret = source.find("return").unwrap();
+            let value = source.find("x end").or(source.find("x else")).unwrap();
+            assert_eq!(
+                parser.errors()[0].spans,
+                vec![(ret, ret + 6), (value, value + 1)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_recover_missing_then() {
+        let source = "program p; var x : integer; begin if x = 1 x := 2 else x := 3 end.";
+        let mut parser = Parser::new(Lexer::new(source)).with_recovery(true);
+        let program = parser.parse_program().unwrap();
+
+        let if_node = program
+            .descendants()
+            .into_iter()
+            .find(|n| n.is_syntax(SyntaxKind::ConditionStatement))
+            .unwrap();
+        assert_eq!(if_node.syntax_children(SyntaxKind::Statement).count(), 2);
+        assert_eq!(parser.errors().len(), 1);
+        assert_eq!(
+            parser.errors()[0].message.as_deref(),
+            Some("missing 'then'")
+        );
+    }
+
+    #[test]
+    fn test_node_text() {
+        let source = "program p;\nvar x : integer;\nbegin\n  x := { one } 1;\n  writeln( x )\nend.";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        assert_eq!(program.text(source), source);
+
+        // 先頭の注釈や末尾の改行も，プログラム全体には含まれる
+        let commented = "{ header }\nprogram p; begin end.\n";
+        let root = Parser::new(Lexer::new(commented)).parse_program().unwrap();
+        assert_eq!(root.span(), Some((0, commented.len())));
+        assert_eq!(root.text(commented), commented);
+        // . の後ろにトークンが続いても，根はソースコード全体になる
+        let trailing = "program p; begin end. {c} x";
+        let root = Parser::new(Lexer::new(trailing)).parse_program().unwrap();
+        assert_eq!(root.text(trailing), trailing);
+        // 木にはトークンでないものは加わらない
+        assert_eq!(
+            root.children()
+                .last()
+                .and_then(|n| n.first_token())
+                .map(|t| t.kind),
+            Some(Kind::Dot)
+        );
+        // 別の短いソースコードを渡しても，はみ出した部分は切り捨てる
+        assert_eq!(root.text("program"), "program");
+        // 文字の途中で切らない
+        let wide = format!("a{}", "あ".repeat(10));
+        assert_eq!(root.text(&wide), &wide[..1 + 3 * 8]);
+
+        let texts = program
+            .descendants()
+            .into_iter()
+            .filter(|n| n.is_syntax(SyntaxKind::Statement))
+            .map(|n| n.text(source))
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["x := { one } 1", "writeln( x )"]);
+
+        // トークンのない空文は空文字列
+        let source = "program p; begin end.";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let empty = program
+            .descendants()
+            .into_iter()
+            .find(|n| n.is_syntax(SyntaxKind::EmptyStatement))
+            .unwrap();
+        assert_eq!(empty.span(), None);
+        assert_eq!(empty.text(source), "");
+    }
+
+    #[test]
+    fn test_node_ids() {
+        let source = "program p; var x : integer; begin x := 1; if x = 1 then writeln(x) end.";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+
+        let nodes = program.descendants();
+        let ids = nodes.iter().map(|n| n.id).collect::<Vec<_>>();
+        assert_eq!(ids, (0..nodes.len()).map(NodeId).collect::<Vec<_>>());
+        for node in nodes.iter() {
+            let found = program.node(node.id).unwrap();
+            assert_eq!(found.id, node.id);
+            assert_eq!(found.kind, node.kind);
+        }
+        assert!(program.node(NodeId(nodes.len())).is_none());
+
+        // 同じ入力からは同じ番号が振られる
+        let again = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        assert_eq!(again, program);
+
+        let assign = nodes
+            .iter()
+            .find(|n| n.is_syntax(SyntaxKind::AssignmentStatement))
+            .unwrap();
+        assert!(program
+            .node(assign.id)
+            .unwrap()
+            .is_syntax(SyntaxKind::AssignmentStatement));
+    }
+
+    #[test]
+    fn test_node_at() {
+        let source = "program p; var x : integer; begin x := 10 end.";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let kind_at = |offset: usize| {
+            let id = program.node_at(offset)?;
+            Some(program.node(id).unwrap().kind.clone())
+        };
+
+        // トークンの内側と先頭では，そのトークン
+        let ten = source.find("10").unwrap();
+        for offset in [ten, ten + 1] {
+            match kind_at(offset) {
+                Some(NodeKind::Token(t)) => assert_eq!(t.value, TokenValue::Integer(10)),
+                other => panic!("{:?}", other),
+            }
+        }
+        // 境界では後ろのトークン
+        let assign = source.find(":=").unwrap();
+        match kind_at(assign) {
+            Some(NodeKind::Token(t)) => assert_eq!(t.kind, Kind::Assign),
+            other => panic!("{:?}", other),
+        }
+        // トークンの間では，両側を含む最も内側の構文
+        assert_eq!(
+            kind_at(assign - 1),
+            Some(NodeKind::Syntax(SyntaxKind::AssignmentStatement))
+        );
+        assert_eq!(kind_at(source.len()), None);
+    }
+
+    #[test]
+    fn test_parse_expression() {
+        let node = Parser::parse_expression("1 + 2 * 3").unwrap();
+        assert_eq!(
+            sexp(&node),
+            "(Expression (SimpleExpression \
+             (Term (Factor (Constant 1))) \
+             (AdditiveOperator Plus) \
+             (Term (Factor (Constant 2)) (MultiplicativeOperator Star) (Factor (Constant 3)))))"
+        );
+
+        // 式の後ろにトークンが残っていればエラー
+        let err = Parser::parse_expression("1 + 2 foo").unwrap_err();
+        assert_eq!(err.expected_token, vec![Kind::Eof]);
+        assert_eq!(err.found.kind, Kind::Name);
+        assert_eq!(err.found.value, TokenValue::String("foo".to_string()));
+    }
+
+    #[test]
+    fn test_operator_alias_is_canonicalized() {
+        let parse = |lexer: Lexer| Parser::new(lexer).parse_program().unwrap();
+        let source = "program p; var a, b : integer; begin if a <> b then a := b end.";
+        let alias = source.replace("<>", "!=");
+
+        let canonical = parse(Lexer::new(source));
+        let aliased = parse(Lexer::new(&alias).with_operator_aliases(true));
+        assert_eq!(aliased, canonical);
+    }
 }
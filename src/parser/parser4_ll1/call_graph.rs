@@ -0,0 +1,266 @@
+use std::collections::{HashMap, HashSet};
+
+use super::diagnostic::Diagnostic;
+use super::st::{NameKey, Node};
+use super::SyntaxKind;
+
+// 呼び出し元
+// メインプログラムの複合文は，どの手続きにも属さない根として扱う
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Caller {
+    Main,
+    Procedure(NameKey),
+}
+
+// 宣言された手続きの名前と，その名前が書かれた位置
+// 比べるときはkeyを，表示するときはnameを使う
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Procedure {
+    pub key: NameKey,
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+// call文から作る，呼び出し元から呼び出し先への有向グラフ
+#[derive(Debug, Clone)]
+pub struct CallGraph {
+    procedures: Vec<Procedure>,
+    edges: HashMap<Caller, Vec<NameKey>>,
+}
+
+/// プログラムの構文木から呼び出しグラフを作る
+pub fn call_graph(program: &Node) -> CallGraph {
+    let mut graph = CallGraph {
+        procedures: vec![],
+        edges: HashMap::new(),
+    };
+    let Some(block) = program.syntax_children(SyntaxKind::Block).next() else {
+        return graph;
+    };
+
+    for sub in block.syntax_children(SyntaxKind::SubprogramDeclaration) {
+        // forward宣言は本体を持たないので，後に来る定義の方を使う
+        if sub
+            .syntax_children(SyntaxKind::CompoundStatement)
+            .next()
+            .is_none()
+        {
+            continue;
+        }
+        let Some(procedure) = procedure_name(sub, program) else {
+            continue;
+        };
+        let mut callees = vec![];
+        for body in sub.syntax_children(SyntaxKind::CompoundStatement) {
+            collect_calls(body, &mut callees);
+        }
+        graph
+            .edges
+            .insert(Caller::Procedure(procedure.key.clone()), callees);
+        graph.procedures.push(procedure);
+    }
+
+    let mut callees = vec![];
+    for body in block.syntax_children(SyntaxKind::CompoundStatement) {
+        collect_calls(body, &mut callees);
+    }
+    graph.edges.insert(Caller::Main, callees);
+
+    graph
+}
+
+impl CallGraph {
+    /// 宣言された手続きを宣言順に返す
+    pub fn procedures(&self) -> &[Procedure] {
+        &self.procedures
+    }
+
+    /// callerが直接呼び出している手続き名を，出現順に返す
+    pub fn callees(&self, caller: &Caller) -> &[NameKey] {
+        self.edges.get(caller).map_or(&[], |v| v.as_slice())
+    }
+
+    /// メインプログラムから呼び出しをたどって到達できる手続き名の集合
+    pub fn reachable_from_main(&self) -> HashSet<NameKey> {
+        let mut reachable = HashSet::new();
+        let mut stack = self.callees(&Caller::Main).to_vec();
+        while let Some(key) = stack.pop() {
+            if reachable.insert(key.clone()) {
+                stack.extend_from_slice(self.callees(&Caller::Procedure(key)));
+            }
+        }
+        reachable
+    }
+
+    /// メインプログラムから到達できない手続きを警告する
+    /// 互いに呼び合っていても，メインから呼ばれていなければ警告の対象になる
+    pub fn unreachable_warnings(&self) -> Vec<Diagnostic> {
+        let reachable = self.reachable_from_main();
+        self.procedures
+            .iter()
+            .filter(|p| !reachable.contains(&p.key))
+            .map(|p| {
+                Diagnostic::warning(
+                    &format!(
+                        "procedure '{}' is never called from the main program",
+                        p.name
+                    ),
+                    p.start,
+                    p.end,
+                )
+            })
+            .collect()
+    }
+}
+
+// 副プログラム宣言の手続き名
+// 名前を番号にした構文木では，根programの名前の表で綴りを引く
+pub(crate) fn procedure_name(sub: &Node, program: &Node) -> Option<Procedure> {
+    let token = sub
+        .syntax_children(SyntaxKind::ProcedureName)
+        .next()?
+        .first_token()?;
+    let key = NameKey::of(token)?;
+    Some(Procedure {
+        name: program.spelling(&key),
+        key,
+        start: token.start,
+        end: token.end,
+    })
+}
+
+// node以下にあるcall文の呼び出し先を集める
+fn collect_calls(node: &Node, callees: &mut Vec<NameKey>) {
+    if node.is_syntax(SyntaxKind::CallStatement) {
+        let key = node
+            .syntax_children(SyntaxKind::ProcedureName)
+            .next()
+            .and_then(|n| n.first_token())
+            .and_then(NameKey::of);
+        callees.extend(key);
+    }
+    for child in node.children() {
+        collect_calls(child, callees);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser4_ll1::Parser;
+    use crate::scan::scan3::Lexer;
+
+    fn graph(source: &str) -> CallGraph {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        call_graph(&program)
+    }
+
+    fn key(name: &str) -> NameKey {
+        NameKey::Spelling(name.to_string())
+    }
+
+    #[test]
+    fn test_called_procedure_is_reachable() {
+        let g = graph(
+            "program p;
+            procedure a; begin call b end;
+            procedure b; begin end;
+            begin call a end.",
+        );
+        assert_eq!(g.callees(&Caller::Main), [key("a")]);
+        assert_eq!(g.callees(&Caller::Procedure(key("a"))), [key("b")]);
+        assert_eq!(
+            g.reachable_from_main(),
+            [key("a"), key("b")].into_iter().collect()
+        );
+        assert_eq!(g.unreachable_warnings(), vec![]);
+    }
+
+    #[test]
+    fn test_forward_declaration_is_not_a_separate_procedure() {
+        let g = graph(
+            "program p;
+            procedure b; forward;
+            procedure a; begin call b end;
+            procedure b; begin call a end;
+            begin call a end.",
+        );
+        let names = g
+            .procedures()
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(g.unreachable_warnings(), vec![]);
+    }
+
+    #[test]
+    fn test_uncalled_procedure_is_warned() {
+        let source = "program p;
+            procedure used; begin end;
+            procedure unused; begin end;
+            begin call used end.";
+        let start = source.find("unused").unwrap();
+
+        let warnings = graph(source).unreachable_warnings();
+        assert_eq!(
+            warnings,
+            vec![Diagnostic::warning(
+                "procedure 'unused' is never called from the main program",
+                start,
+                start + 6
+            )]
+        );
+    }
+
+    #[test]
+    fn test_mutually_recursive_uncalled_procedures_are_warned() {
+        let warnings = graph(
+            "program p;
+            var n : integer;
+            procedure even; begin if n > 0 then call odd end;
+            procedure odd; begin n := n - 1; call even end;
+            begin n := 4 end.",
+        )
+        .unreachable_warnings();
+
+        let names = warnings
+            .iter()
+            .map(|w| w.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "procedure 'even' is never called from the main program",
+                "procedure 'odd' is never called from the main program",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interned_names() {
+        // 名前を番号にしても番号どうしで突き合わせ，警告には綴りを出す
+        let source = "program p;
+            procedure used; begin end;
+            procedure unused; begin end;
+            begin call used end.";
+        let mut parser = Parser::new(Lexer::new(source).with_interning(true));
+        let program = parser.parse_program().unwrap();
+        assert_eq!(parser.interner(), program.interner());
+        let g = call_graph(&program);
+
+        let names = g
+            .procedures()
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["used", "unused"]);
+        assert!(matches!(g.callees(&Caller::Main), [NameKey::Symbol(_)]));
+        assert_eq!(g.callees(&Caller::Main)[0], g.procedures()[0].key);
+        assert_eq!(
+            g.unreachable_warnings(),
+            graph(source).unreachable_warnings()
+        );
+    }
+}
@@ -0,0 +1,342 @@
+// 手続きの中で定数を伝播し，値が分かる式を畳み込む
+// x := 5; y := x + 1 なら，y に代入される式の値は6と分かる
+
+use std::collections::HashMap;
+
+use super::st::{NameKey, Node, NodeId, NodeKind};
+use super::SyntaxKind;
+
+use crate::scan::scan3::{Kind, TokenValue};
+
+// MPPLの整数の範囲．畳み込んだ結果がこの外に出る式は，値が分からないものとする
+const MIN_INTEGER: i32 = -32768;
+const MAX_INTEGER: i32 = 32767;
+
+// 変数名から，その時点で分かっている値への対応
+type Values = HashMap<NameKey, i32>;
+
+// 定数伝播の結果
+// 値が分かった式（式・単純式・項・因子）を，ノード番号から引ける
+#[derive(Debug, Clone, Default)]
+pub struct Constants {
+    values: HashMap<NodeId, i32>,
+}
+
+impl Constants {
+    /// 番号idの式の値．値が分からない式や，式でないノードはNone
+    pub fn value(&self, id: NodeId) -> Option<i32> {
+        self.values.get(&id).copied()
+    }
+
+    /// 代入文statementの右辺の値
+    pub fn assigned_value(&self, statement: &Node) -> Option<i32> {
+        let right = statement.syntax_children(SyntaxKind::Expression).next()?;
+        self.value(right.id)
+    }
+}
+
+/// プログラムの各手続きの本体とメインプログラムで，定数を伝播しながら式を畳み込む
+///
+/// 変数の値は文の順にたどって求める
+/// - 代入で値の分かる式を代入すると，その値になる．値の分からない式を代入すると，分からなくなる
+/// - read で読み込んだ変数と，手続きを呼び出した後のすべての変数は，分からなくなる
+/// - if文の後では，どの分岐を通っても同じ値の変数だけが値を保つ
+/// - whileの条件と本体では，本体の中で値が変わりうる変数は分からないものとする
+pub fn propagate_constants(program: &Node) -> Constants {
+    let mut constants = Constants::default();
+    let Some(block) = program.syntax_children(SyntaxKind::Block).next() else {
+        return constants;
+    };
+
+    let bodies = block
+        .syntax_children(SyntaxKind::SubprogramDeclaration)
+        .flat_map(|sub| sub.syntax_children(SyntaxKind::CompoundStatement))
+        .chain(block.syntax_children(SyntaxKind::CompoundStatement));
+    for body in bodies {
+        // 変数の初期値は分からないので，手続きごとに何も分からない状態から始める
+        statement(body, &mut Values::new(), &mut constants);
+    }
+    constants
+}
+
+// 文をたどり，valuesを文の実行後に分かっている変数の値にする
+fn statement(node: &Node, values: &mut Values, constants: &mut Constants) {
+    let NodeKind::Syntax(syntax) = node.kind else {
+        return;
+    };
+    match syntax {
+        SyntaxKind::Statement => {
+            for child in node.children() {
+                statement(child, values, constants);
+            }
+        }
+        SyntaxKind::CompoundStatement => {
+            for child in node.syntax_children(SyntaxKind::Statement) {
+                statement(child, values, constants);
+            }
+        }
+        SyntaxKind::AssignmentStatement => {
+            let left = node.syntax_children(SyntaxKind::LeftPart).next();
+            if let Some(left) = left {
+                evaluate_all(left, values, constants);
+            }
+            let value = node
+                .syntax_children(SyntaxKind::Expression)
+                .next()
+                .and_then(|right| evaluate(right, values, constants));
+            if let Some(name) = left.and_then(variable_name) {
+                match value {
+                    Some(v) => values.insert(name, v),
+                    None => values.remove(&name),
+                };
+            }
+        }
+        SyntaxKind::ConditionStatement => {
+            for n in node.syntax_children(SyntaxKind::Expression) {
+                evaluate(n, values, constants);
+            }
+            let before = values.clone();
+            let mut after: Option<Values> = None;
+            let branches = node
+                .syntax_children(SyntaxKind::Statement)
+                .collect::<Vec<_>>();
+            for branch in branches.iter() {
+                let mut v = before.clone();
+                statement(branch, &mut v, constants);
+                after = Some(match after {
+                    Some(a) => join(&a, &v),
+                    None => v,
+                });
+            }
+            let after = after.unwrap_or_default();
+            // else がなければ，どの分岐も通らない場合と合わせる
+            *values = if branches.len() < 2 {
+                join(&after, &before)
+            } else {
+                after
+            };
+        }
+        SyntaxKind::IterationStatement => {
+            // 2回目以降の繰り返しでは，本体で代入された値になっている
+            forget_assigned(node, values);
+            for n in node.syntax_children(SyntaxKind::Expression) {
+                evaluate(n, values, constants);
+            }
+            let mut v = values.clone();
+            for body in node.syntax_children(SyntaxKind::Statement) {
+                statement(body, &mut v, constants);
+            }
+        }
+        SyntaxKind::CallStatement | SyntaxKind::InputStatement | SyntaxKind::OutputStatement => {
+            evaluate_all(node, values, constants);
+            forget_assigned(node, values);
+        }
+        _ => {}
+    }
+}
+
+// 両方で同じ値を持つ変数だけを残す
+fn join(a: &Values, b: &Values) -> Values {
+    a.iter()
+        .filter(|(name, v)| b.get(*name) == Some(v))
+        .map(|(name, v)| (name.clone(), *v))
+        .collect()
+}
+
+// node以下で値が変わりうる変数を，値の分かっている変数から外す
+// 手続きは大域変数や参照渡しの引数を書き換えうるので，呼び出しがあればすべて外す
+fn forget_assigned(node: &Node, values: &mut Values) {
+    for n in node.descendants() {
+        if n.is_syntax(SyntaxKind::CallStatement) {
+            values.clear();
+            return;
+        }
+        if n.is_syntax(SyntaxKind::AssignmentStatement) {
+            let left = n.syntax_children(SyntaxKind::LeftPart).next();
+            if let Some(name) = left.and_then(variable_name) {
+                values.remove(&name);
+            }
+        } else if n.is_syntax(SyntaxKind::InputStatement) {
+            for v in n.syntax_children(SyntaxKind::Variable) {
+                if let Some(name) = variable_name(v) {
+                    values.remove(&name);
+                }
+            }
+        }
+    }
+}
+
+// node以下で，他の式に含まれない式をすべて畳み込む
+fn evaluate_all(node: &Node, values: &Values, constants: &mut Constants) {
+    if node.is_syntax(SyntaxKind::Expression) {
+        evaluate(node, values, constants);
+        return;
+    }
+    for child in node.children() {
+        evaluate_all(child, values, constants);
+    }
+}
+
+// 式を畳み込み，値が分かればconstantsに記録して返す
+// 一部の値が分からなくても，分かる部分式はすべて記録するので，子はすべてたどる
+fn evaluate(node: &Node, values: &Values, constants: &mut Constants) -> Option<i32> {
+    let NodeKind::Syntax(syntax) = node.kind else {
+        return None;
+    };
+    let children = node.children();
+    let value = match syntax {
+        // 関係演算の結果は真理値なので，整数としては扱わない
+        SyntaxKind::Expression => {
+            let values = children
+                .iter()
+                .map(|c| evaluate(c, values, constants))
+                .collect::<Vec<_>>();
+            match values[..] {
+                [v] => v,
+                _ => None,
+            }
+        }
+        SyntaxKind::SimpleExpression | SyntaxKind::Term => {
+            let mut acc: Option<i32> = None;
+            let mut op = None;
+            let mut first = true;
+            for child in children {
+                match &child.kind {
+                    // 先頭の符号
+                    NodeKind::Token(t) => op = Some(t.kind),
+                    NodeKind::Syntax(SyntaxKind::AdditiveOperator)
+                    | NodeKind::Syntax(SyntaxKind::MultiplicativeOperator) => {
+                        op = child.first_token().map(|t| t.kind);
+                    }
+                    NodeKind::Syntax(_) => {
+                        let v = evaluate(child, values, constants);
+                        acc = if first {
+                            match op {
+                                Some(Kind::Minus) => v.map(|v| -v),
+                                _ => v,
+                            }
+                        } else {
+                            match (acc, v) {
+                                (Some(a), Some(b)) => apply(op, a, b),
+                                _ => None,
+                            }
+                        };
+                        first = false;
+                    }
+                }
+            }
+            acc
+        }
+        SyntaxKind::Factor => {
+            let values = children
+                .iter()
+                .map(|c| evaluate(c, values, constants))
+                .collect::<Vec<_>>();
+            match children {
+                // 変数 | 定数
+                [_] => values[0],
+                // "(" 式 ")"
+                [open, _, _] if open.first_token().is_some_and(|t| t.kind == Kind::LParen) => {
+                    values[1]
+                }
+                // "not" 因子 や 標準型 "(" 式 ")" は，整数の値としては扱わない
+                _ => None,
+            }
+        }
+        SyntaxKind::Variable => {
+            // 添字の式も畳み込む
+            for child in children.iter().skip(1) {
+                evaluate(child, values, constants);
+            }
+            variable_name(node).and_then(|name| values.get(&name).copied())
+        }
+        SyntaxKind::Constant => match node.first_token().map(|t| (t.kind, &t.value)) {
+            Some((Kind::UnsignedInteger, TokenValue::Integer(n))) => i32::try_from(*n).ok(),
+            _ => None,
+        },
+        _ => None,
+    };
+    let value = value.filter(|v| (MIN_INTEGER..=MAX_INTEGER).contains(v));
+    if let Some(v) = value {
+        constants.values.insert(node.id, v);
+    }
+    value
+}
+
+// 2項演算を計算する．0で割る場合や，真理値の演算は値が分からないものとする
+fn apply(op: Option<Kind>, a: i32, b: i32) -> Option<i32> {
+    match op? {
+        Kind::Plus => a.checked_add(b),
+        Kind::Minus => a.checked_sub(b),
+        Kind::Star => a.checked_mul(b),
+        Kind::Div => a.checked_div(b),
+        _ => None,
+    }
+}
+
+// 添字のない変数であれば，その名前
+fn variable_name(node: &Node) -> Option<NameKey> {
+    match node.tokens()[..] {
+        [token] => NameKey::of(token),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser4_ll1::Parser;
+    use crate::scan::scan3::Lexer;
+
+    // bodyの中の代入文の右辺の値を，出現順に返す
+    fn assigned_values(body: &str) -> Vec<Option<i32>> {
+        let source = format!("program p; var x, y : integer; begin {} end.", body);
+        let program = Parser::new(Lexer::new(&source)).parse_program().unwrap();
+        let constants = propagate_constants(&program);
+        program
+            .descendants()
+            .into_iter()
+            .filter(|n| n.is_syntax(SyntaxKind::AssignmentStatement))
+            .map(|n| constants.assigned_value(n))
+            .collect()
+    }
+
+    #[test]
+    fn test_propagate() {
+        assert_eq!(
+            assigned_values("x := 5; y := x + 1"),
+            vec![Some(5), Some(6)]
+        );
+        assert_eq!(
+            assigned_values("x := 2 * (3 + 4); y := -x div 4 - 1"),
+            vec![Some(14), Some(-4)]
+        );
+    }
+
+    #[test]
+    fn test_invalidation() {
+        assert_eq!(
+            assigned_values("x := 5; read(x); y := x + 1"),
+            vec![Some(5), None]
+        );
+        // 分岐で値が異なれば，if文の後では分からない
+        assert_eq!(
+            assigned_values("x := 5; if y > 0 then x := 6; y := x"),
+            vec![Some(5), Some(6), None]
+        );
+        assert_eq!(
+            assigned_values("x := 5; if y > 0 then x := 5 else x := 5; y := x"),
+            vec![Some(5), Some(5), Some(5), Some(5)]
+        );
+        // 繰り返しの中では，本体で代入される変数の値は分からない
+        assert_eq!(
+            assigned_values("x := 5; while y > 0 do begin y := x; x := 1 end"),
+            vec![Some(5), None, Some(1)]
+        );
+        // 範囲を超える値や，0での割り算は畳み込まない
+        assert_eq!(
+            assigned_values("x := 32767 + 1; y := 1 div 0"),
+            vec![None, None]
+        );
+    }
+}
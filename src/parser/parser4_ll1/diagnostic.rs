@@ -0,0 +1,114 @@
+// 構文木を調べる各パスが報告する診断
+// 誤りと警告は重大度が違うだけなので，1つの型で表す
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    // プログラムとして意味をなさない
+    Error,
+    // 構文としては正しいが，おそらく意図していない
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: &str, start: usize, end: usize) -> Self {
+        Self {
+            severity,
+            message: message.to_string(),
+            start,
+            end,
+        }
+    }
+
+    pub fn error(message: &str, start: usize, end: usize) -> Self {
+        Self::new(Severity::Error, message, start, end)
+    }
+
+    pub fn warning(message: &str, start: usize, end: usize) -> Self {
+        Self::new(Severity::Warning, message, start, end)
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+/// 警告をすべて誤りに格上げする
+/// 各パスの診断を集めた後にかけるので，どのパスの警告も同じように扱われる
+pub fn deny_warnings(diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    diagnostics
+        .into_iter()
+        .map(|d| Diagnostic {
+            severity: Severity::Error,
+            ..d
+        })
+        .collect()
+}
+
+/// 診断の中に誤りがあるか．あればコンパイルは失敗とする
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(|d| d.is_error())
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}: {} at {}..{}",
+            severity, self.message, self.start, self.end
+        )
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deny_warnings() {
+        use crate::parser::parser4_ll1::call_graph::call_graph;
+        use crate::parser::parser4_ll1::Parser;
+        use crate::scan::scan3::Lexer;
+
+        // 警告しか出ないプログラム
+        let source = "program p; procedure unused; begin end; begin end.";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let diagnostics = call_graph(&program).unreachable_warnings();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(!has_errors(&diagnostics));
+
+        let denied = deny_warnings(diagnostics.clone());
+        assert!(has_errors(&denied));
+        assert_eq!(denied[0].message, diagnostics[0].message);
+        assert_eq!(
+            (denied[0].start, denied[0].end),
+            (diagnostics[0].start, diagnostics[0].end)
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let error = Diagnostic::error("division by zero", 3, 4);
+        let warning = Diagnostic::warning("statement is never executed", 5, 11);
+        assert!(error.is_error());
+        assert!(!warning.is_error());
+        assert_eq!(error.to_string(), "error: division by zero at 3..4");
+        assert_eq!(
+            warning.to_string(),
+            "warning: statement is never executed at 5..11"
+        );
+    }
+}
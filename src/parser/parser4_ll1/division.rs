@@ -0,0 +1,180 @@
+// 0で割る div を見つける
+// 除数が0の定数である場合に加え，定数伝播で0になることが分かっている式で割る場合も見つける
+
+use super::constants::{propagate_constants, Constants};
+use super::diagnostic::Diagnostic;
+use super::st::{NameKey, Node};
+use super::SyntaxKind;
+
+use crate::scan::scan3::{Kind, Token, TokenValue};
+
+/// 除数が0と分かる div をエラーにする
+/// 除数の値は propagate_constants で求める．同じ div は1度だけエラーにする
+pub fn check_division_by_zero(program: &Node) -> Vec<Diagnostic> {
+    let constants = propagate_constants(program);
+    let mut errors = vec![];
+    check_expression(program, program, &constants, &mut errors);
+    errors
+}
+
+// node以下の div の除数が0と分かるものをエラーにする
+// 除数の変数名は，根programの名前の表で綴りにして示す
+fn check_expression(
+    node: &Node,
+    program: &Node,
+    constants: &Constants,
+    errors: &mut Vec<Diagnostic>,
+) {
+    for term in node.descendants() {
+        if !term.is_syntax(SyntaxKind::Term) {
+            continue;
+        }
+        // 項は 因子 { 乗法演算子 因子 } と並ぶので，演算子とその右の因子を組にして見る
+        for pair in term.children().windows(2) {
+            let is_div = pair[0].is_syntax(SyntaxKind::MultiplicativeOperator)
+                && pair[0].first_token().is_some_and(|t| t.kind == Kind::Div);
+            let divisor = &pair[1];
+            if !is_div || constants.value(divisor.id) != Some(0) {
+                continue;
+            }
+            let message = if is_zero_literal(divisor) {
+                "division by zero".to_string()
+            } else {
+                match unparenthesized_name(divisor) {
+                    Some(key) => format!(
+                        "division by zero: '{}' is always 0 here",
+                        program.spelling(&key)
+                    ),
+                    None => "division by zero: the divisor is always 0 here".to_string(),
+                }
+            };
+            if let Some((start, end)) = divisor.span() {
+                errors.push(Diagnostic::error(&message, start, end));
+            }
+        }
+    }
+}
+
+// 外側の括弧を除いたnodeのトークン列が，ちょうど1つの変数名であればその名前
+fn unparenthesized_name(node: &Node) -> Option<NameKey> {
+    match unparenthesized(node)[..] {
+        [token] => NameKey::of(token),
+        _ => None,
+    }
+}
+
+// 外側の括弧を除いたnodeが，定数の0であるか
+fn is_zero_literal(node: &Node) -> bool {
+    match unparenthesized(node)[..] {
+        [token] => token.kind == Kind::UnsignedInteger && token.value == TokenValue::Integer(0),
+        _ => false,
+    }
+}
+
+fn unparenthesized(node: &Node) -> Vec<&Token> {
+    let tokens = node.tokens();
+    let mut s = &tokens[..];
+    while s.len() >= 2 && s[0].kind == Kind::LParen && s[s.len() - 1].kind == Kind::RParen {
+        s = &s[1..s.len() - 1];
+    }
+    s.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser4_ll1::Parser;
+    use crate::scan::scan3::Lexer;
+
+    fn check(body: &str) -> (String, Vec<Diagnostic>) {
+        let source = format!("program p; var x, d : integer; begin {} end.", body);
+        let program = Parser::new(Lexer::new(&source)).parse_program().unwrap();
+        let errors = check_division_by_zero(&program);
+        (source, errors)
+    }
+
+    #[test]
+    fn test_literal_zero_divisor() {
+        let (source, errors) = check("x := x div 0");
+        let zero = source.find("0 end").unwrap();
+        assert_eq!(
+            errors,
+            vec![Diagnostic::error("division by zero", zero, zero + 1)]
+        );
+        assert_eq!(check("x := x div (0)").1.len(), 1);
+    }
+
+    #[test]
+    fn test_propagated_zero_divisor() {
+        let (source, errors) = check("d := 0; x := x div d");
+        let d = source.find("div d").unwrap() + 4;
+        assert_eq!(
+            errors,
+            vec![Diagnostic::error(
+                "division by zero: 'd' is always 0 here",
+                d,
+                d + 1
+            )]
+        );
+
+        // if文のどちらかで代入されていれば，0とは限らない
+        assert_eq!(
+            check("d := 0; if x > 0 then d := 1; x := x div d").1,
+            vec![]
+        );
+        assert_eq!(
+            check("d := 0; if x > 0 then d := 0 else x := 1; x := x div d")
+                .1
+                .len(),
+            1
+        );
+        // 繰り返しの中では，後で代入される変数は0とは限らない
+        assert_eq!(
+            check("d := 0; while x > 0 do begin x := x div d; d := 1 end").1,
+            vec![]
+        );
+        assert_eq!(check("d := 0; read(d); x := x div d").1, vec![]);
+    }
+
+    #[test]
+    fn test_folded_zero_divisor() {
+        let (source, errors) = check("d := 3; x := x div (d - 3)");
+        let divisor = source.find("(d - 3)").unwrap();
+        assert_eq!(
+            errors,
+            vec![Diagnostic::error(
+                "division by zero: the divisor is always 0 here",
+                divisor,
+                divisor + "(d - 3)".len()
+            )]
+        );
+        // 同じ div は，何度たどられても1度だけ報告する
+        assert_eq!(
+            check("d := 1 - 1; while x > 0 do x := x div 0; x := x div d")
+                .1
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_nonzero_divisor() {
+        assert_eq!(check("d := 2; x := x div d; x := x div 3").1, vec![]);
+        assert_eq!(check("x := x div d").1, vec![]);
+        assert_eq!(check("d := 0; d := d + 1; x := x div d").1, vec![]);
+        // 0を掛けるのは誤りではない
+        assert_eq!(check("x := x * 0").1, vec![]);
+    }
+
+    #[test]
+    fn test_interned_names() {
+        // 名前を番号にしても値を伝播し，エラーには綴りを出す
+        let body = "d := 0; x := x div d; d := 1; x := x div d";
+        let (source, errors) = check(body);
+        let lexer = Lexer::new(&source).with_interning(true);
+        let program = Parser::new(lexer).parse_program().unwrap();
+        assert_eq!(check_division_by_zero(&program), errors);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "division by zero: 'd' is always 0 here");
+    }
+}
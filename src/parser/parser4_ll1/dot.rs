@@ -0,0 +1,77 @@
+// 構文木をGraphvizのDOT形式で書き出す
+// dot -Tpng などで画像にすると，授業やデバッグで構文木の形を確かめられる
+
+use super::st::{Node, NodeKind};
+
+use crate::scan::scan3::TokenValue;
+
+/// 構文木をDOT形式の文字列にする
+/// ノードはノード番号 (NodeId) で n0, n1, ... と名付けるので，同じ入力からは同じ出力になる
+/// 構文のノードは構文の種類を，トークンのノードはトークンの種類と値をラベルにする
+pub fn to_dot(program: &Node) -> String {
+    let mut buf = String::from("digraph syntax_tree {\n");
+    for node in program.descendants() {
+        let label = match &node.kind {
+            NodeKind::Syntax(syntax) => format!("{:?}", syntax),
+            NodeKind::Token(token) => match &token.value {
+                TokenValue::None => format!("{:?}", token.kind),
+                TokenValue::Integer(n) => format!("{:?} {}", token.kind, n),
+                TokenValue::String(s) => format!("{:?} {:?}", token.kind, s),
+                TokenValue::Ident(symbol) => format!("{:?} #{}", token.kind, symbol.0),
+            },
+        };
+        let shape = match node.kind {
+            NodeKind::Syntax(_) => "box",
+            NodeKind::Token(_) => "ellipse",
+        };
+        buf.push_str(&format!(
+            "    n{} [label=\"{}\", shape={}];\n",
+            node.id.0,
+            escape(&label),
+            shape
+        ));
+        for child in node.children() {
+            buf.push_str(&format!("    n{} -> n{};\n", node.id.0, child.id.0));
+        }
+    }
+    buf.push_str("}\n");
+    buf
+}
+
+// DOTの文字列の中では，" と \ をエスケープする
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser4_ll1::Parser;
+    use crate::scan::scan3::Lexer;
+
+    #[test]
+    fn test_to_dot() {
+        let source = "program p; var x : integer; begin if x = 1 then writeln('one') end.";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let dot = to_dot(&program);
+
+        assert!(dot.starts_with("digraph syntax_tree {\n"));
+        assert!(dot.contains("n0 [label=\"Program\", shape=box];"));
+        for label in [
+            "\"ConditionStatement\"",
+            "\"If\"",
+            "\"Then\"",
+            "\"Equal\"",
+            "\"UnsignedInteger 1\"",
+            "\"Name \\\"x\\\"\"",
+            "\"String \\\"one\\\"\"",
+        ] {
+            assert!(dot.contains(label), "{}", label);
+        }
+
+        // 根以外のノードには，親からの辺がちょうど1本ある
+        let nodes = program.descendants().len();
+        assert_eq!(dot.matches(" -> ").count(), nodes - 1);
+        assert_eq!(to_dot(&program), dot);
+    }
+}
@@ -15,6 +15,10 @@ pub struct SyntaxError {
     pub(crate) expected_token: Vec<scan3::Kind>,
     pub(crate) expected_syntax: Vec<SyntaxKind>,
     pub(crate) found: Token,
+    // 期待したトークンの列挙だけでは分かりにくい誤りに対する説明
+    pub(crate) message: Option<String>,
+    // 説明に関係するソースコード上の範囲 (start, end)
+    pub(crate) spans: Vec<(usize, usize)>,
 }
 
 impl SyntaxError {
@@ -33,8 +37,18 @@ impl SyntaxError {
             expected_token,
             expected_syntax,
             found,
+            message: None,
+            spans: vec![],
         }
     }
+
+    /// よくある誤りに対して，説明と関係する範囲を持つエラーを作る
+    pub fn with_message(parser: &Parser, message: &str, spans: &[(usize, usize)]) -> Self {
+        let mut err = Self::new(parser, &[], &[]);
+        err.message = Some(message.to_string());
+        err.spans = spans.to_vec();
+        err
+    }
 }
 
 impl std::fmt::Display for SyntaxError {
@@ -47,7 +61,15 @@ impl std::fmt::Display for SyntaxError {
             .join(", ");
         let found = format!("{:?}", self.found);
 
-        let binding = FIRST_SETS;
+        if let Some(message) = &self.message {
+            return write!(
+                f,
+                "source code:\n\n {} \n\n {} at {:?} but found {:?}",
+                self.lexeicalized_source, message, self.spans, found
+            );
+        }
+
+        let binding = &FIRST_SETS;
         let tokens = binding
             .iter()
             .filter(|x| self.expected_syntax.contains(&x.symbol))
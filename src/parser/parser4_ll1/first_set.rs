@@ -1,4 +1,4 @@
-use std::cell::LazyCell;
+use std::sync::LazyLock;
 
 use crate::scan::scan3::Kind;
 
@@ -17,8 +17,8 @@ impl FirstSet {
     }
 }
 
-pub(crate) const FIRST_SETS: LazyCell<Vec<FirstSet>> = {
-    LazyCell::new(|| {
+pub(crate) static FIRST_SETS: LazyLock<Vec<FirstSet>> = {
+    LazyLock::new(|| {
         vec![
             FirstSet::new(
                 SyntaxKind::Program,
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use super::call_graph::{procedure_name, Procedure};
+use super::diagnostic::Diagnostic;
+use super::st::{NameKey, Node};
+use super::SyntaxKind;
+
+use crate::scan::scan3::{Kind, TokenValue};
+
+// 仮引数1つ分の型を，型を表すトークンの並びで表す
+// 例えば array[5] of integer なら [Array, LBracket, 5, RBracket, Of, Integer]
+type ParameterType = Vec<(Kind, TokenValue)>;
+
+/// forward宣言とその後の定義が対応しているかを調べる
+/// 定義の仮引数がforward宣言と異なる場合と，forward宣言した手続きが定義されない場合にエラーを返す
+pub fn check_forward_declarations(program: &Node) -> Vec<Diagnostic> {
+    let mut errors = vec![];
+    let Some(block) = program.syntax_children(SyntaxKind::Block).next() else {
+        return errors;
+    };
+
+    // まだ定義が現れていないforward宣言
+    let mut pending: HashMap<NameKey, (Procedure, Vec<ParameterType>)> = HashMap::new();
+    let mut order = vec![];
+    for sub in block.syntax_children(SyntaxKind::SubprogramDeclaration) {
+        let Some(procedure) = procedure_name(sub, program) else {
+            continue;
+        };
+        let signature = signature(sub);
+        let is_forward = sub
+            .children()
+            .iter()
+            .any(|n| n.first_token().is_some_and(|t| t.kind == Kind::Forward));
+
+        if is_forward {
+            order.push(procedure.key.clone());
+            pending.insert(procedure.key.clone(), (procedure, signature));
+        } else if let Some((_, declared)) = pending.remove(&procedure.key) {
+            if declared != signature {
+                errors.push(Diagnostic::error(
+                    &format!(
+                        "parameters of '{}' do not match its forward declaration",
+                        procedure.name
+                    ),
+                    procedure.start,
+                    procedure.end,
+                ));
+            }
+        }
+    }
+
+    for key in order {
+        if let Some((procedure, _)) = pending.remove(&key) {
+            errors.push(Diagnostic::error(
+                &format!(
+                    "procedure '{}' is declared forward but never defined",
+                    procedure.name
+                ),
+                procedure.start,
+                procedure.end,
+            ));
+        }
+    }
+    errors
+}
+
+// 副プログラム宣言の仮引数の型を，仮引数の順に並べる
+fn signature(sub: &Node) -> Vec<ParameterType> {
+    let mut types = vec![];
+    let Some(params) = sub.syntax_children(SyntaxKind::FormalParameters).next() else {
+        return types;
+    };
+    let mut names = 0;
+    for child in params.children() {
+        if child.is_syntax(SyntaxKind::VariableNames) {
+            names = child.syntax_children(SyntaxKind::VariableName).count();
+        } else if child.is_syntax(SyntaxKind::Type) {
+            let ty = child
+                .tokens()
+                .iter()
+                .map(|t| (t.kind, t.value.clone()))
+                .collect::<ParameterType>();
+            types.extend(std::iter::repeat_n(ty, names));
+        }
+    }
+    types
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser4_ll1::Parser;
+    use crate::scan::scan3::Lexer;
+
+    fn check(source: &str) -> Vec<Diagnostic> {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        check_forward_declarations(&program)
+    }
+
+    #[test]
+    fn test_mutual_recursion_with_forward() {
+        let errors = check(
+            "program p;
+            var n : integer;
+            procedure odd(a : integer); forward;
+            procedure even(a : integer); begin if a > 0 then call odd(a - 1) end;
+            procedure odd(a : integer); begin if a > 0 then call even(a - 1) end;
+            begin call even(4) end.",
+        );
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn test_mismatched_definition() {
+        let source = "program p;
+            procedure q(a, b : integer; c : char); forward;
+            procedure q(a : integer; b, c : char); begin end;
+            begin call q(1, 'x', 'y') end.";
+        let definition = source.find("q(a : integer;").unwrap();
+        assert_eq!(
+            check(source),
+            vec![Diagnostic::error(
+                "parameters of 'q' do not match its forward declaration",
+                definition,
+                definition + 1
+            )]
+        );
+
+        // 名前のまとめ方が違っても，型の並びが同じなら一致とみなす
+        let errors = check(
+            "program p;
+            procedure q(a, b : integer; c : char); forward;
+            procedure q(x : integer; y : integer; z : char); begin end;
+            begin call q(1, 2, 'z') end.",
+        );
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn test_forward_never_defined() {
+        let source = "program p;
+            procedure q; forward;
+            begin call q end.";
+        let declaration = source.find("q;").unwrap();
+        assert_eq!(
+            check(source),
+            vec![Diagnostic::error(
+                "procedure 'q' is declared forward but never defined",
+                declaration,
+                declaration + 1
+            )]
+        );
+    }
+
+    #[test]
+    fn test_interned_names() {
+        // 名前を番号にしても，forward宣言と定義を突き合わせ，エラーには綴りを出す
+        let source = "program p;
+            procedure q; forward;
+            procedure r(a : integer); forward;
+            procedure r(a : char); begin call q end;
+            begin call r('x') end.";
+        let interned = |source: &str| {
+            let lexer = Lexer::new(source).with_interning(true);
+            let program = Parser::new(lexer).parse_program().unwrap();
+            check_forward_declarations(&program)
+        };
+        let messages = interned(source)
+            .into_iter()
+            .map(|e| e.message)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "parameters of 'r' do not match its forward declaration",
+                "procedure 'q' is declared forward but never defined",
+            ]
+        );
+        assert_eq!(interned(source), check(source));
+    }
+}
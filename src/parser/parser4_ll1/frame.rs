@@ -0,0 +1,127 @@
+// 手続きの活性化レコードの大きさを求める
+// 後段で手続きの入口にフレームを確保するときに使う
+
+use super::st::{Node, NodeKind};
+use super::SyntaxKind;
+
+use crate::scan::scan3::{Kind, TokenValue};
+
+/// 手続き宣言procedureの活性化レコードに必要な語数
+///
+/// - 仮引数は参照渡しなので，型によらずアドレスの1語
+/// - 局所変数は，標準型が1語，配列型 array [N] of ... がN語
+/// - 式の途中の値を置く一時領域として，本体の式で演算が入れ子になる深さの最大値だけの語数
+pub fn frame_size(procedure: &Node) -> usize {
+    let parameters = procedure
+        .syntax_children(SyntaxKind::FormalParameters)
+        .flat_map(|p| p.syntax_children(SyntaxKind::VariableNames))
+        .map(|names| names.syntax_children(SyntaxKind::VariableName).count())
+        .sum::<usize>();
+
+    let locals = procedure
+        .syntax_children(SyntaxKind::VariableDeclaration)
+        .map(declaration_size)
+        .sum::<usize>();
+
+    let temporaries = procedure
+        .syntax_children(SyntaxKind::CompoundStatement)
+        .flat_map(|body| body.descendants())
+        .filter(|n| n.is_syntax(SyntaxKind::Expression))
+        .map(operation_depth)
+        .max()
+        .unwrap_or(0);
+
+    parameters + locals + temporaries
+}
+
+// 変数宣言部で宣言される変数の語数の合計
+// 変数宣言部は 変数名の並び ":" 型 ";" の繰り返しなので，並びとその後ろの型を組にして見る
+fn declaration_size(declaration: &Node) -> usize {
+    let children = declaration.children();
+    children
+        .iter()
+        .enumerate()
+        .filter(|(_, n)| n.is_syntax(SyntaxKind::VariableNames))
+        .map(|(i, names)| {
+            let count = names.syntax_children(SyntaxKind::VariableName).count();
+            let size = children[i..]
+                .iter()
+                .find(|n| n.is_syntax(SyntaxKind::Type))
+                .map_or(1, type_size);
+            count * size
+        })
+        .sum()
+}
+
+// 型の値1つ分の語数
+fn type_size(ty: &Node) -> usize {
+    let Some(array) = ty.syntax_children(SyntaxKind::ArrayType).next() else {
+        return 1;
+    };
+    array
+        .tokens()
+        .iter()
+        .find_map(|t| match (t.kind, &t.value) {
+            (Kind::UnsignedInteger, TokenValue::Integer(n)) => Some(*n as usize),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+// nodeを計算するときに，同時に置いておく途中の値の数
+// 演算子を持つ式・単純式・項は，右の被演算子を計算する間，左の値を置いておく
+fn operation_depth(node: &Node) -> usize {
+    let NodeKind::Syntax(syntax) = node.kind else {
+        return 0;
+    };
+    let children = node.children();
+    let has_operator = match syntax {
+        SyntaxKind::Expression => children.len() > 1,
+        SyntaxKind::SimpleExpression => children
+            .iter()
+            .any(|n| n.is_syntax(SyntaxKind::AdditiveOperator)),
+        SyntaxKind::Term => children
+            .iter()
+            .any(|n| n.is_syntax(SyntaxKind::MultiplicativeOperator)),
+        _ => false,
+    };
+    let inner = children.iter().map(operation_depth).max().unwrap_or(0);
+    inner + usize::from(has_operator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser4_ll1::Parser;
+    use crate::scan::scan3::Lexer;
+
+    fn frame_sizes(source: &str) -> Vec<usize> {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        program
+            .descendants()
+            .into_iter()
+            .filter(|n| n.is_syntax(SyntaxKind::SubprogramDeclaration))
+            .map(frame_size)
+            .collect()
+    }
+
+    #[test]
+    fn test_locals() {
+        // 局所変数 1 + 1 + 5 語と，x + (y * 2) の途中の値 2 語
+        let source = "program p;
+            procedure q; var x, y : integer; a : array [5] of integer;
+            begin x := x + (y * 2) end;
+            begin call q end.";
+        assert_eq!(frame_sizes(source), vec![9]);
+    }
+
+    #[test]
+    fn test_parameters_and_temporaries() {
+        let source = "program p; var v : integer;
+            procedure q(m, n : integer; c : char); begin writeln(m) end;
+            procedure r; var a : array [3] of boolean; begin a[1] := v + 1 > 2 * v end;
+            begin call q(v, v, 'c'); call r end.";
+        // 仮引数 3 語 / 局所変数 3 語と，関係演算の左の値と 2 * v の左の値 2 語
+        assert_eq!(frame_sizes(source), vec![3, 5]);
+    }
+}
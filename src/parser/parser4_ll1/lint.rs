@@ -0,0 +1,200 @@
+// 構文木を見て，誤りではないがおそらく意図していない書き方を警告する
+
+use super::constants::{propagate_constants, Constants};
+use super::diagnostic::Diagnostic;
+use super::st::Node;
+use super::SyntaxKind;
+
+use crate::scan::scan3::{Kind, Token, TokenValue};
+
+/// x := x や x := x + 0 のように，変数の値を変えない代入を警告する
+/// 0 や 1 は定数伝播で値が分かる式でもよく，x := x + (1 - 1) も警告する
+/// x := x + 1 のように値が変わる代入は警告しない
+pub fn self_assignment_warnings(program: &Node) -> Vec<Diagnostic> {
+    let constants = propagate_constants(program);
+    program
+        .descendants()
+        .into_iter()
+        .filter(|n| n.is_syntax(SyntaxKind::AssignmentStatement))
+        .filter(|n| is_self_assignment(n, &constants))
+        .filter_map(|n| {
+            let (start, end) = n.span()?;
+            Some(Diagnostic::warning(
+                "assignment does not change the variable",
+                start,
+                end,
+            ))
+        })
+        .collect()
+}
+
+/// 同じ複合文の中で，return や break の後ろに書かれた文を警告する
+/// 警告するのは，実行されない文のうち最初の1つだけ
+/// if文の中の return のように，条件によって実行される return や break の後ろは警告しない
+pub fn unreachable_statement_warnings(program: &Node) -> Vec<Diagnostic> {
+    let mut warnings = vec![];
+    for compound in program.descendants() {
+        if !compound.is_syntax(SyntaxKind::CompoundStatement) {
+            continue;
+        }
+        let mut statements = compound.syntax_children(SyntaxKind::Statement);
+        let exits = statements.by_ref().any(|s| {
+            s.children().iter().any(|n| {
+                n.is_syntax(SyntaxKind::ReturnStatement) || n.is_syntax(SyntaxKind::ExitStatement)
+            })
+        });
+        if !exits {
+            continue;
+        }
+        // end の直前の ; の後ろにある空文は，実行される文ではないので飛ばす
+        if let Some((start, end)) = statements.find_map(|s| s.span()) {
+            warnings.push(Diagnostic::warning(
+                "statement is never executed",
+                start,
+                end,
+            ));
+        }
+    }
+    warnings
+}
+
+// 代入文の右辺が，左辺そのものか，左辺に0を足し引きしたものか，左辺に1を掛け割りしたものか
+// 左辺は変数なので，トークンの並びが一致すれば同じ変数を指す
+// 足し引きや掛け割りする相手は，x + (1 - 1) のように定数伝播で値が分かるものも含める
+fn is_self_assignment(statement: &Node, constants: &Constants) -> bool {
+    let (Some(left), Some(right)) = (
+        statement.syntax_children(SyntaxKind::LeftPart).next(),
+        statement.syntax_children(SyntaxKind::Expression).next(),
+    ) else {
+        return false;
+    };
+    let left = spellings(&left.tokens());
+    if spellings(&right.tokens()) == left {
+        return true;
+    }
+
+    // 関係演算子のない式の，ただ1つの単純式
+    let simple = match right.children() {
+        [simple] if simple.is_syntax(SyntaxKind::SimpleExpression) => simple,
+        _ => return false,
+    };
+    let is_left = |n: &Node| spellings(&n.tokens()) == left;
+    let value = |n: &Node| constants.value(n.id);
+    match simple.children() {
+        // x + 0 や 0 + x, x - 0
+        [a, op, b] if a.is_syntax(SyntaxKind::Term) => match operator(op) {
+            Some(Kind::Plus) => {
+                (is_left(a) && value(b) == Some(0)) || (value(a) == Some(0) && is_left(b))
+            }
+            Some(Kind::Minus) => is_left(a) && value(b) == Some(0),
+            _ => false,
+        },
+        // x * 1 や 1 * x, x div 1
+        [term] => match term.children() {
+            [a, op, b] => match operator(op) {
+                Some(Kind::Star) => {
+                    (is_left(a) && value(b) == Some(1)) || (value(a) == Some(1) && is_left(b))
+                }
+                Some(Kind::Div) => is_left(a) && value(b) == Some(1),
+                _ => false,
+            },
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// 加法演算子・乗法演算子のノードであれば，その演算子の種類
+fn operator(node: &Node) -> Option<Kind> {
+    let is_operator = node.is_syntax(SyntaxKind::AdditiveOperator)
+        || node.is_syntax(SyntaxKind::MultiplicativeOperator);
+    if !is_operator {
+        return None;
+    }
+    node.first_token().map(|t| t.kind)
+}
+
+// 位置を除いた，トークンの種類と値の並び
+fn spellings(tokens: &[&Token]) -> Vec<(Kind, TokenValue)> {
+    tokens.iter().map(|t| (t.kind, t.value.clone())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser4_ll1::Parser;
+    use crate::scan::scan3::Lexer;
+
+    fn warnings(statement: &str) -> Vec<Diagnostic> {
+        let source = format!(
+            "program p; var x, i : integer; a : array [10] of integer; begin {} end.",
+            statement
+        );
+        let program = Parser::new(Lexer::new(&source)).parse_program().unwrap();
+        self_assignment_warnings(&program)
+    }
+
+    #[test]
+    fn test_self_assignment() {
+        let source = "program p; var x : integer; begin x := x end.";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let start = source.find("x :=").unwrap();
+        assert_eq!(
+            self_assignment_warnings(&program),
+            vec![Diagnostic::warning(
+                "assignment does not change the variable",
+                start,
+                start + "x := x".len()
+            )]
+        );
+
+        assert_eq!(warnings("a[i] := a[i]").len(), 1);
+        assert_eq!(warnings("x := x + 0").len(), 1);
+        assert_eq!(warnings("x := 1 * x").len(), 1);
+        assert_eq!(warnings("a[i] := a[i] div 1").len(), 1);
+        // 定数伝播で0や1と分かる式も同じ
+        assert_eq!(warnings("x := x + (1 - 1)").len(), 1);
+        assert_eq!(warnings("i := 1; x := i * x").len(), 1);
+    }
+
+    #[test]
+    fn test_unreachable_statement() {
+        let source = "program p; var x : integer;
+            procedure q; begin return; x := 1; x := 2 end;
+            begin call q end.";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let start = source.find("x := 1").unwrap();
+        assert_eq!(
+            unreachable_statement_warnings(&program),
+            vec![Diagnostic::warning(
+                "statement is never executed",
+                start,
+                start + "x := 1".len()
+            )]
+        );
+
+        let source = "program p; var x : integer;
+            begin
+                while x > 0 do begin x := x - 1; break; end;
+                while x < 9 do begin if x = 5 then break; x := x + 1 end
+            end.";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        assert_eq!(unreachable_statement_warnings(&program), vec![]);
+
+        // if文の中の return の後ろは，条件が偽なら実行される
+        let source = "program p; var x : integer;
+            procedure q; begin if x = 0 then return; x := 1 end;
+            begin call q end.";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        assert_eq!(unreachable_statement_warnings(&program), vec![]);
+    }
+
+    #[test]
+    fn test_meaningful_update_is_not_warned() {
+        assert_eq!(warnings("x := x + 1"), vec![]);
+        assert_eq!(warnings("x := 0 - x"), vec![]);
+        assert_eq!(warnings("a[i] := a[i + 1]"), vec![]);
+        assert_eq!(warnings("x := x * 1 + 1"), vec![]);
+        assert_eq!(warnings("i := x"), vec![]);
+    }
+}
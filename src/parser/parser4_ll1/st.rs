@@ -1,20 +1,199 @@
 use super::SyntaxKind;
 
-use crate::scan::scan3::Token;
+use crate::scan::interner::{Interner, Symbol};
+use crate::scan::line_index::floor_char_boundary;
+use crate::scan::scan3::{Kind, Token, TokenValue};
 
-#[derive(Debug, Clone)]
+// 構文木のノードを識別する番号
+// パースが終わった時点で，根から行きがけ順に0から振る
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub usize);
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Node {
+    pub id: NodeId,
     pub kind: NodeKind,
     pub children: Option<Vec<Node>>,
+    // プログラム全体のノードだけが持つ，ソースコード全体の範囲
+    // 先頭や末尾の注釈・空白はどのトークンにも含まれないので，トークンからは求められない
+    source_range: Option<(usize, usize)>,
+    // 同じく根だけが持つ，名前を番号にして字句解析したときの名前の表
+    interner: Option<Box<Interner>>,
 }
 
 impl Node {
     pub(crate) fn new(kind: NodeKind, children: Option<Vec<Node>>) -> Self {
-        Self { kind, children }
+        Self {
+            id: NodeId(0),
+            kind,
+            children,
+            source_range: None,
+            interner: None,
+        }
+    }
+
+    /// このノードの範囲を，ソースコード全体 [0, source_len) にする
+    pub(crate) fn cover_source(&mut self, source_len: usize) {
+        self.source_range = Some((0, source_len));
+    }
+
+    /// 名前の番号の綴りを引けるように，字句解析で作った名前の表を持たせる
+    pub(crate) fn attach_interner(&mut self, interner: Interner) {
+        self.interner = Some(Box::new(interner));
+    }
+
+    /// with_interning で字句解析した木の根であれば，名前の表
+    pub fn interner(&self) -> Option<&Interner> {
+        self.interner.as_deref()
+    }
+
+    /// 名前のキーの綴り
+    /// 番号のキーは，このノード（根）が持つ名前の表で引く．表がなければ #番号 とする
+    pub fn spelling(&self, key: &NameKey) -> String {
+        match key {
+            NameKey::Spelling(name) => name.clone(),
+            NameKey::Symbol(symbol) => match self.interner() {
+                Some(interner) => interner.resolve(*symbol).to_string(),
+                None => format!("#{}", symbol.0),
+            },
+        }
+    }
+
+    /// このノードを根として，行きがけ順に番号を振り直す
+    /// 同じ入力からは常に同じ番号になる
+    pub(crate) fn assign_ids(&mut self) {
+        fn assign(node: &mut Node, next: &mut usize) {
+            node.id = NodeId(*next);
+            *next += 1;
+            for child in node.children.iter_mut().flatten() {
+                assign(child, next);
+            }
+        }
+        assign(self, &mut 0);
+    }
+
+    /// 番号からノードを引く
+    /// 行きがけ順の番号なので，部分木の番号は連続しており，子を二分探索でたどれる
+    pub fn node(&self, id: NodeId) -> Option<&Node> {
+        let mut node = self;
+        loop {
+            if node.id == id {
+                return Some(node);
+            }
+            let children = node.children();
+            let i = children.partition_point(|c| c.id <= id);
+            node = children.get(i.checked_sub(1)?)?;
+        }
+    }
+
+    /// offsetを範囲 [start, end) に含む，最も内側のノードの番号
+    /// トークンの境界では，offsetから始まる側のトークンを含むノードになる
+    /// トークンの間の空白や注釈の上では，そこを囲む構文のノードになる
+    pub fn node_at(&self, offset: usize) -> Option<NodeId> {
+        let (start, end) = self.span()?;
+        if !(start..end).contains(&offset) {
+            return None;
+        }
+        Some(
+            self.children()
+                .iter()
+                .find_map(|c| c.node_at(offset))
+                .unwrap_or(self.id),
+        )
+    }
+
+    /// このノードを含む，部分木のすべてのノードを行きがけ順に返す
+    pub fn descendants(&self) -> Vec<&Node> {
+        let mut nodes = vec![self];
+        for child in self.children() {
+            nodes.extend(child.descendants());
+        }
+        nodes
+    }
+
+    pub fn children(&self) -> &[Node] {
+        self.children.as_deref().unwrap_or(&[])
+    }
+
+    pub fn is_syntax(&self, syntax: SyntaxKind) -> bool {
+        matches!(self.kind, NodeKind::Syntax(k) if k == syntax)
+    }
+
+    /// 指定した構文の子ノードを，出現順に返す
+    pub fn syntax_children(&self, syntax: SyntaxKind) -> impl Iterator<Item = &Node> {
+        self.children().iter().filter(move |n| n.is_syntax(syntax))
+    }
+
+    /// このノード以下で最初に現れるトークン
+    pub fn first_token(&self) -> Option<&Token> {
+        match &self.kind {
+            NodeKind::Token(t) => Some(t),
+            NodeKind::Syntax(_) => self.children().iter().find_map(|n| n.first_token()),
+        }
+    }
+
+    /// このノード以下で最後に現れるトークン
+    pub fn last_token(&self) -> Option<&Token> {
+        match &self.kind {
+            NodeKind::Token(t) => Some(t),
+            NodeKind::Syntax(_) => self.children().iter().rev().find_map(|n| n.last_token()),
+        }
+    }
+
+    /// このノード以下のトークンを出現順に返す
+    pub fn tokens(&self) -> Vec<&Token> {
+        match &self.kind {
+            NodeKind::Token(t) => vec![t],
+            NodeKind::Syntax(_) => self.children().iter().flat_map(|n| n.tokens()).collect(),
+        }
+    }
+
+    /// 最初のトークンの始まりから最後のトークンの終わりまでの範囲
+    /// 空文のようにトークンを持たないノードはNone
+    /// parse_program が返す根のノードは，先頭と末尾の注釈や空白も含めたソースコード全体の範囲になる
+    pub fn span(&self) -> Option<(usize, usize)> {
+        if self.source_range.is_some() {
+            return self.source_range;
+        }
+        Some((self.first_token()?.start, self.last_token()?.end))
+    }
+
+    /// このノードに対応するソースコードをそのまま返す
+    /// ノードの内側にある空白や注釈も含まれるが，最初のトークンより前と最後のトークンより後ろは含まない
+    /// ただし parse_program が返す根のノードは，ソースコード全体になる
+    /// 範囲がsourceの外にはみ出す場合は，sourceの中に収まる部分だけを返す
+    /// 範囲の端が文字の途中にある場合は，その文字の先頭までにする
+    pub fn text<'s>(&self, source: &'s str) -> &'s str {
+        let Some((start, end)) = self.span() else {
+            return "";
+        };
+        let end = floor_char_boundary(source, end);
+        let start = floor_char_boundary(source, start.min(end));
+        &source[start..end]
+    }
+}
+
+/// 名前を比べるためのキー
+/// 綴りを持つ名前は綴り，with_interning で番号にした名前は番号で比べるので，番号どうしの比較は文字列を作らない
+/// 1つの構文木の名前はどちらか一方だけになる．表示するときは Node::spelling で綴りに戻す
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NameKey {
+    Spelling(String),
+    Symbol(Symbol),
+}
+
+impl NameKey {
+    /// 名前のトークンであれば，そのキー
+    pub fn of(token: &Token) -> Option<NameKey> {
+        match (&token.kind, &token.value) {
+            (Kind::Name, TokenValue::String(name)) => Some(NameKey::Spelling(name.clone())),
+            (Kind::Name, TokenValue::Ident(symbol)) => Some(NameKey::Symbol(*symbol)),
+            _ => None,
+        }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum NodeKind {
     Token(Token),
     Syntax(SyntaxKind),
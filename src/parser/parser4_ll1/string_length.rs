@@ -0,0 +1,69 @@
+// 文字列定数の長さを調べる
+// CASL IIでは文字列を決まった大きさの出力バッファを通して書き出すので，長すぎる文字列はあふれてしまう
+
+use super::diagnostic::Diagnostic;
+use super::st::Node;
+
+use crate::scan::scan3::{Kind, TokenValue};
+
+// 出力バッファの大きさの既定値
+pub const DEFAULT_MAX_STRING_LENGTH: usize = 255;
+
+/// max_string_lengthより長い文字列定数をエラーにする
+/// 長さはシングルクォートを重ねた '' を1文字と数えた，TokenValue::Stringの文字数
+pub fn check_string_lengths(program: &Node, max_string_length: usize) -> Vec<Diagnostic> {
+    program
+        .tokens()
+        .into_iter()
+        .filter(|t| t.kind == Kind::String)
+        .filter_map(|t| match &t.value {
+            TokenValue::String(s) if s.chars().count() > max_string_length => {
+                Some(Diagnostic::error(
+                    &format!(
+                        "string literal is {} characters long; the limit is {}",
+                        s.chars().count(),
+                        max_string_length
+                    ),
+                    t.start,
+                    t.end,
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser4_ll1::Parser;
+    use crate::scan::scan3::Lexer;
+
+    fn check(literal: &str) -> Vec<Diagnostic> {
+        let source = format!("program p; begin writeln({}) end.", literal);
+        let program = Parser::new(Lexer::new(&source)).parse_program().unwrap();
+        check_string_lengths(&program, DEFAULT_MAX_STRING_LENGTH)
+    }
+
+    #[test]
+    fn test_string_length() {
+        let at_limit = format!("'{}'", "a".repeat(255));
+        assert_eq!(check(&at_limit), vec![]);
+
+        let over = format!("'{}'", "a".repeat(256));
+        let start = "program p; begin writeln(".len();
+        assert_eq!(
+            check(&over),
+            vec![Diagnostic::error(
+                "string literal is 256 characters long; the limit is 255",
+                start,
+                start + over.len()
+            )]
+        );
+
+        // '' は1文字なので，書かれた文字数が上限を超えていても値は上限に収まる
+        let doubled = format!("'{}'''", "a".repeat(254));
+        assert_eq!(doubled.len() - 2, 256);
+        assert_eq!(check(&doubled), vec![]);
+    }
+}
@@ -0,0 +1,168 @@
+// 配列全体の代入や比較を見つける
+// MPPLでは配列は要素ごとにしか代入・比較できないので，a := b や a = b は誤り
+
+use std::collections::HashMap;
+
+use super::diagnostic::Diagnostic;
+use super::st::{NameKey, Node};
+use super::SyntaxKind;
+
+use crate::scan::scan3::Kind;
+
+// 名前から，その名前が配列型で宣言されているかへの対応
+type Scope = HashMap<NameKey, bool>;
+
+/// 添字のない配列の変数を，代入の左辺・右辺や関係演算子の被演算子に使っていればエラーにする
+/// エラーの位置は := や関係演算子
+/// 変数の型は宣言から求める．手続きの中では，仮引数と局所変数が同じ名前の大域変数を隠す
+pub fn check_whole_array_operations(program: &Node) -> Vec<Diagnostic> {
+    let mut errors = vec![];
+    let Some(block) = program.syntax_children(SyntaxKind::Block).next() else {
+        return errors;
+    };
+
+    let mut global = Scope::new();
+    for declaration in block.syntax_children(SyntaxKind::VariableDeclaration) {
+        declare(declaration, &mut global);
+    }
+    for sub in block.syntax_children(SyntaxKind::SubprogramDeclaration) {
+        let mut scope = global.clone();
+        for child in sub.children() {
+            if child.is_syntax(SyntaxKind::FormalParameters)
+                || child.is_syntax(SyntaxKind::VariableDeclaration)
+            {
+                declare(child, &mut scope);
+            }
+        }
+        for body in sub.syntax_children(SyntaxKind::CompoundStatement) {
+            check(body, &scope, &mut errors);
+        }
+    }
+    for body in block.syntax_children(SyntaxKind::CompoundStatement) {
+        check(body, &global, &mut errors);
+    }
+    errors
+}
+
+// 変数宣言部や仮引数部で宣言される名前を，型が配列かどうかとともにscopeに加える
+// どちらも 変数名の並び ":" 型 の繰り返しなので，並びとその後ろの型を組にして見る
+fn declare(declaration: &Node, scope: &mut Scope) {
+    let mut names = vec![];
+    for child in declaration.children() {
+        if child.is_syntax(SyntaxKind::VariableNames) {
+            names = child
+                .syntax_children(SyntaxKind::VariableName)
+                .filter_map(|n| n.first_token().and_then(NameKey::of))
+                .collect();
+        } else if child.is_syntax(SyntaxKind::Type) {
+            let is_array = child
+                .syntax_children(SyntaxKind::ArrayType)
+                .next()
+                .is_some();
+            for name in names.drain(..) {
+                scope.insert(name, is_array);
+            }
+        }
+    }
+}
+
+// node以下の代入文と関係演算子で，添字のない配列を使っているものをエラーにする
+fn check(node: &Node, scope: &Scope, errors: &mut Vec<Diagnostic>) {
+    for n in node.descendants() {
+        let children = n.children();
+        let (message, operands, operator) = if n.is_syntax(SyntaxKind::AssignmentStatement) {
+            // 左辺部 ":=" 式
+            let [left, assign, right] = children else {
+                continue;
+            };
+            ("cannot assign whole arrays", [left, right], assign)
+        } else if n.is_syntax(SyntaxKind::Expression) {
+            // 単純式 関係演算子 単純式
+            let [left, operator, right] = children else {
+                continue;
+            };
+            ("cannot compare whole arrays", [left, right], operator)
+        } else {
+            continue;
+        };
+        if !operands.iter().any(|o| is_whole_array(o, scope)) {
+            continue;
+        }
+        if let Some((start, end)) = operator.span() {
+            errors.push(Diagnostic::error(message, start, end));
+        }
+    }
+}
+
+// 外側の括弧を除いたnodeが，添字のない配列の変数であるか
+fn is_whole_array(node: &Node, scope: &Scope) -> bool {
+    let tokens = node.tokens();
+    let mut s = &tokens[..];
+    while s.len() >= 2 && s[0].kind == Kind::LParen && s[s.len() - 1].kind == Kind::RParen {
+        s = &s[1..s.len() - 1];
+    }
+    match s {
+        [token] => NameKey::of(token).is_some_and(|key| scope.get(&key) == Some(&true)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser4_ll1::Parser;
+    use crate::scan::scan3::Lexer;
+
+    fn check(body: &str) -> (String, Vec<Diagnostic>) {
+        let source = format!(
+            "program p; var i, j, x : integer; a, b : array [5] of integer; begin {} end.",
+            body
+        );
+        let program = Parser::new(Lexer::new(&source)).parse_program().unwrap();
+        let errors = check_whole_array_operations(&program);
+        (source, errors)
+    }
+
+    #[test]
+    fn test_whole_array_assignment() {
+        let (source, errors) = check("a := b");
+        let assign = source.find(":=").unwrap();
+        assert_eq!(
+            errors,
+            vec![Diagnostic::error(
+                "cannot assign whole arrays",
+                assign,
+                assign + 2
+            )]
+        );
+        assert_eq!(check("x := a").1.len(), 1);
+    }
+
+    #[test]
+    fn test_whole_array_comparison() {
+        let (source, errors) = check("if a = b then x := 1");
+        let equal = source.find("= b").unwrap();
+        assert_eq!(
+            errors,
+            vec![Diagnostic::error(
+                "cannot compare whole arrays",
+                equal,
+                equal + 1
+            )]
+        );
+        assert_eq!(check("while (a) <> x do x := 1").1.len(), 1);
+    }
+
+    #[test]
+    fn test_element_wise() {
+        assert_eq!(check("a[i] := b[j]").1, vec![]);
+        assert_eq!(check("if a[i] = b[j] then x := a[i] + 1").1, vec![]);
+
+        // 局所変数は，同じ名前の大域変数の配列を隠す
+        let source = "program p; var a : array [5] of integer;
+            procedure q; var a : integer; begin a := 1 end;
+            begin call q end.";
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        assert_eq!(check_whole_array_operations(&program), vec![]);
+    }
+}
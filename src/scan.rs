@@ -1,3 +1,6 @@
 // pub mod scan1;
 // pub mod scan2;
 pub mod scan3;
+pub mod line_index;
+pub mod interner;
+pub mod token_cache;
@@ -0,0 +1,83 @@
+// 名前の綴りを1か所にまとめて持ち，トークンからは小さな番号で参照する
+// 同じ名前が何度も現れても文字列は1つしか作らず，名前の比較も番号の比較で済む
+
+use std::collections::HashMap;
+
+// 名前の番号
+// 同じInternerから得た番号どうしは，綴りが同じとき，かつそのときに限り等しい
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(pub u32);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Interner {
+    symbols: HashMap<String, Symbol>,
+    names: Vec<String>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// nameの番号を返す．初めて現れた綴りには新しい番号を振る
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(name) {
+            return *symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.symbols.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    /// 番号から綴りを引く
+    /// 別のInternerから得た番号を渡すとpanicする
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+
+    /// これまでに現れた名前の数
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::scan3::{Kind, Lexer, TokenValue};
+
+    #[test]
+    fn test_intern() {
+        let mut interner = Interner::new();
+        let foo = interner.intern("foo");
+        let bar = interner.intern("bar");
+        assert_eq!(interner.intern("foo"), foo);
+        assert_ne!(foo, bar);
+        assert_eq!(interner.resolve(foo), "foo");
+        assert_eq!(interner.resolve(bar), "bar");
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn test_lexer_interning() {
+        let mut lexer = Lexer::new("foo := foo + bar").with_interning(true);
+        let tokens = lexer.analyze();
+        let symbols = tokens
+            .iter()
+            .filter(|t| t.kind == Kind::Name)
+            .map(|t| match t.value {
+                TokenValue::Ident(symbol) => symbol,
+                _ => panic!("{:?}", t),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(symbols[0], symbols[1]);
+        assert_ne!(symbols[0], symbols[2]);
+        assert_eq!(lexer.interner().unwrap().resolve(symbols[0]), "foo");
+        assert_eq!(lexer.interner().unwrap().resolve(symbols[2]), "bar");
+    }
+}
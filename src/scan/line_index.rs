@@ -0,0 +1,160 @@
+// バイトオフセットと，エディタ上で見える行・列を対応付ける
+// トークンの start/end はバイトオフセットのまま変えず，表示するときだけ列に変換する
+
+const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// offsetをsourceの範囲に収め，文字の途中であれば，その文字の先頭まで戻す
+/// 範囲を切り出す前に通せば，ずれたオフセットでも切り出しで異常終了しない
+pub fn floor_char_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+pub struct LineIndex<'a> {
+    source: &'a str,
+    // 各行の先頭のバイトオフセット
+    line_starts: Vec<usize>,
+    tab_width: usize,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+        Self {
+            source,
+            line_starts,
+            tab_width: DEFAULT_TAB_WIDTH,
+        }
+    }
+
+    /// タブ1つを何列として数えるかを指定する
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = tab_width.max(1);
+        self
+    }
+
+    /// offsetを1始まりの(行, 列)に変換する
+    /// 列はタブを展開した表示上の列
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_of(offset);
+        (line + 1, self.column(offset) + 1)
+    }
+
+    /// start..endを含む行を表示し，その下に^で範囲を示した文字列を返す
+    /// 表示する行のタブは空白に展開するので，端末のタブ幅に関係なく^がそろう
+    pub fn render_caret(&self, start: usize, end: usize) -> String {
+        let line_start = self.line_starts[self.line_of(start)];
+        let line_end = self.source[line_start..]
+            .find('\n')
+            .map_or(self.source.len(), |i| line_start + i);
+        let text = &self.source[line_start..line_end];
+        let text = text.strip_suffix('\r').unwrap_or(text);
+
+        // 範囲が行をまたぐ場合は，行末までを示す
+        // 取り除いた \r や改行の上，ソースコードの外を指すstartも，行末に寄せる
+        // 文字の途中を指す場合は，その文字の先頭に寄せる
+        let line_end = line_start + text.len();
+        let start = floor_char_boundary(self.source, start.min(line_end));
+        let end = floor_char_boundary(self.source, end.clamp(start, line_end));
+        let col = self.column(start);
+        let width = (self.column(end) - col).max(1);
+
+        format!(
+            "{}\n{}{}",
+            self.expand_tabs(text),
+            " ".repeat(col),
+            "^".repeat(width)
+        )
+    }
+
+    // offsetを含む行（0始まり）
+    fn line_of(&self, offset: usize) -> usize {
+        let offset = offset.min(self.source.len());
+        self.line_starts.partition_point(|&s| s <= offset) - 1
+    }
+
+    // offsetの行内での表示上の列（0始まり）
+    fn column(&self, offset: usize) -> usize {
+        let offset = floor_char_boundary(self.source, offset);
+        let line_start = self.line_starts[self.line_of(offset)];
+        self.columns(&self.source[line_start..offset], 0)
+    }
+
+    // 列colから始めてsを置いたときの，最後の列
+    fn columns(&self, s: &str, mut col: usize) -> usize {
+        for c in s.chars() {
+            if c == '\t' {
+                col += self.tab_width - col % self.tab_width;
+            } else {
+                col += 1;
+            }
+        }
+        col
+    }
+
+    fn expand_tabs(&self, s: &str) -> String {
+        let mut buf = String::new();
+        for c in s.chars() {
+            if c == '\t' {
+                let n = self.tab_width - self.columns(&buf, 0) % self.tab_width;
+                buf.push_str(&" ".repeat(n));
+            } else {
+                buf.push(c);
+            }
+        }
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_col() {
+        let source = "program p;\n\tbegin\n\t\tx := 1\n";
+        let offset = source.find("x").unwrap();
+
+        // バイトオフセットは変わらないが，列はタブ幅に応じて展開される
+        assert_eq!(LineIndex::new(source).line_col(offset), (3, 9));
+        let index = LineIndex::new(source).with_tab_width(8);
+        assert_eq!(index.line_col(offset), (3, 17));
+        assert_eq!(index.line_col(source.find("begin").unwrap()), (2, 9));
+        assert_eq!(index.line_col(0), (1, 1));
+    }
+
+    #[test]
+    fn test_render_caret_with_tab() {
+        let source = "begin\n\tx := 1\nend";
+        let start = source.find(":=").unwrap();
+        let rendered = LineIndex::new(source).render_caret(start, start + 2);
+
+        let lines = rendered.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "    x := 1");
+        assert_eq!(lines[1], "      ^^");
+        let caret = lines[1].find('^').unwrap();
+        assert_eq!(&lines[0][caret..caret + 2], ":=");
+    }
+
+    #[test]
+    fn test_render_caret_past_line_end() {
+        // \r の後ろの \n の上
+        assert_eq!(LineIndex::new("ab\r\ncd").render_caret(3, 3), "ab\n  ^");
+        // ソースコードの外
+        assert_eq!(LineIndex::new("abc").render_caret(10, 12), "abc\n   ^");
+    }
+    #[test]
+    fn test_multibyte_offsets() {
+        // 文字の途中を指すオフセットは，その文字の先頭とみなす
+        let index = LineIndex::new("あいう");
+        assert_eq!(index.line_col(1), (1, 1));
+        assert_eq!(index.line_col(4), (1, 2));
+        assert_eq!(index.render_caret(1, 5), "あいう\n^");
+        assert_eq!(floor_char_boundary("あいう", 8), 6);
+        assert_eq!(floor_char_boundary("あいう", 100), 9);
+    }
+}
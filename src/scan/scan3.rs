@@ -1,4 +1,10 @@
-use std::{collections::HashSet, iter::Peekable, str::Chars, sync::LazyLock};
+use std::{
+    collections::{HashMap, HashSet},
+    str::Chars,
+};
+
+use super::interner::{Interner, Symbol};
+use super::line_index::LineIndex;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Token {
@@ -13,6 +19,8 @@ pub enum TokenValue {
     None,
     Integer(u32),
     String(String),
+    // with_interningを有効にしたときの名前．綴りはLexer::interner()で引く
+    Ident(Symbol),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -50,6 +58,10 @@ pub enum Kind {
     True,
     False,
     Break,
+    Forward,
+    // 方言用のキーワード．既定では予約語ではなく，with_keywordsで有効にする
+    Repeat,
+    Until,
     // 以下記号
     Plus,
     Minus,
@@ -73,13 +85,87 @@ pub enum Kind {
     Unknown,
 }
 
-// 記号のトークンについて1文字のみの記号か，2文字以上の可能性がある記号かを保持する
-// つまり，最初の文字を読んだ段階で確定できるものを集めた配列
-static SYMBOLS_LEN_1: LazyLock<HashSet<&str>> = LazyLock::new(|| {
-    vec!["+", "-", "*", "=", "(", ")", "[", "]", ".", ",", ";"]
-        .into_iter()
-        .collect::<HashSet<&str>>()
-});
+// 符号なし整数として書ける最大値
+const MAX_UNSIGNED_INTEGER: u32 = 32767;
+
+// 字句解析中に見つかった誤り
+// 誤りがあっても字句解析は止めず，トークンを作ったうえで記録しておく
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexError {
+    // 閉じるシングルクォートがないままソースコードが終わった文字列
+    UnterminatedString { start: usize, end: usize },
+    // 符号なし整数の範囲 (0..=32767) を超える整数
+    IntegerOutOfRange { start: usize, end: usize },
+    // どの字句の始まりにもならない文字 (@ や # など)
+    UnknownCharacter { start: usize, end: usize },
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LexError::UnterminatedString { start, end } => {
+                write!(f, "unterminated string at {}..{}", start, end)
+            }
+            LexError::IntegerOutOfRange { start, end } => write!(
+                f,
+                "integer out of range (max {}) at {}..{}",
+                MAX_UNSIGNED_INTEGER, start, end
+            ),
+            LexError::UnknownCharacter { start, end } => {
+                write!(f, "unknown character at {}..{}", start, end)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// トークン列は作らずに，字句の誤りだけを返す
+pub fn lint_lexical(source: &str) -> Vec<LexError> {
+    let mut lexer = Lexer::new(source);
+    while lexer.read_next_token().kind != Kind::Eof {}
+    lexer.errors
+}
+
+/// offsetを範囲 [start, end) に含むトークンを，位置順に並んだtokensから二分探索で探す
+/// あるトークンの終わりと次のトークンの始まりが重なる位置では，後ろのトークンを返す
+/// トークンの間の空白や注釈の上，および幅0のEofの位置ではNone
+pub fn token_at(tokens: &[Token], offset: usize) -> Option<&Token> {
+    let i = tokens.partition_point(|t| t.start <= offset);
+    let token = tokens.get(i.checked_sub(1)?)?;
+    (offset < token.end).then_some(token)
+}
+
+/// トークン列を，バージョン間でdiffを取りやすい1行1トークンの形式にする
+///
+/// 各行は `開始行:開始列-終了行:終了列  種類  値` で，トークンの位置順に並ぶ
+/// - 行と列は1始まり．列は文字単位で数え，タブも1列とする．終了位置はトークンの最後の文字の次
+/// - 種類はKindの名前をそのまま書く
+/// - 値は，整数は10進数，名前と文字列はダブルクォートで囲んだエスケープ表記で書き，値がなければ省く
+pub fn canonical_dump(source: &str, tokens: &[Token]) -> String {
+    let index = LineIndex::new(source).with_tab_width(1);
+    let mut tokens = tokens.iter().collect::<Vec<_>>();
+    tokens.sort_by_key(|t| (t.start, t.end));
+
+    let mut buf = String::new();
+    for token in tokens {
+        let (start_line, start_col) = index.line_col(token.start);
+        let (end_line, end_col) = index.line_col(token.end);
+        let mut line = format!(
+            "{}:{}-{}:{}  {:?}",
+            start_line, start_col, end_line, end_col, token.kind
+        );
+        match &token.value {
+            TokenValue::None => {}
+            TokenValue::Integer(n) => line.push_str(&format!("  {}", n)),
+            TokenValue::String(s) => line.push_str(&format!("  {:?}", s)),
+            TokenValue::Ident(symbol) => line.push_str(&format!("  #{}", symbol.0)),
+        }
+        buf.push_str(&line);
+        buf.push('\n');
+    }
+    buf
+}
 
 fn match_keyword(ident: &str) -> Kind {
     if ident.len() == 1 || ident.len() > 10 {
@@ -114,6 +200,7 @@ fn match_keyword(ident: &str) -> Kind {
         "true" => Kind::True,
         "false" => Kind::False,
         "break" => Kind::Break,
+        "forward" => Kind::Forward,
         _ => Kind::Name,
     }
 }
@@ -142,21 +229,106 @@ fn match_symbol(symbol: &str) -> Kind {
     }
 }
 
+// 方言で使われる演算子の別表記
+// 字句解析の段階で正規の種類に変換するので，パーサー以降は1つの表記しか見ない
+fn match_operator_alias(symbol: &str) -> Kind {
+    match symbol {
+        "!=" => Kind::NotEq,
+        _ => Kind::Unknown,
+    }
+}
+
+// cが記号の1文字目になりうるか
+// 別表記にしか使わない ! は，別表記を受け付けるときに Lexer::token で見る
+fn starts_symbol(c: char) -> bool {
+    matches!(
+        c,
+        '+' | '-' | '*' | '=' | '<' | '>' | '(' | ')' | '[' | ']' | ':' | '.' | ',' | ';'
+    )
+}
+
+// 複製すると，元の字句解析を進めずに先のトークンを読める
+#[derive(Clone)]
 pub struct Lexer<'a> {
     pub source: &'a str,
-    pub chars: Peekable<Chars<'a>>,
-    // chars: Chars<'a>,
+    // Peekableで包むと残りの位置が分からなくなるので，Charsのまま持ち，先読みはpeek()で行う
+    chars: Chars<'a>,
+    operator_aliases: bool,
+    allow_line_comments: bool,
+    concatenate_strings: bool,
+    raw_strings: bool,
+    // Noneなら名前の綴りをそのままトークンに持たせる
+    interner: Option<Interner>,
+    // 既定の予約語に加えるものと，既定の予約語から外すもの
+    extra_keywords: HashMap<String, Kind>,
+    removed_keywords: HashSet<String>,
+    errors: Vec<LexError>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(source: &'a str) -> Self {
         Self {
             source,
-            // chars: source.chars(),
-            chars: source.chars().peekable(),
+            chars: source.chars(),
+            operator_aliases: false,
+            allow_line_comments: false,
+            concatenate_strings: false,
+            raw_strings: false,
+            interner: None,
+            extra_keywords: HashMap::new(),
+            removed_keywords: HashSet::new(),
+            errors: vec![],
         }
     }
 
+    /// != を <> として扱うなど，演算子の別表記を受け付けるかどうか
+    pub fn with_operator_aliases(mut self, enabled: bool) -> Self {
+        self.operator_aliases = enabled;
+        self
+    }
+
+    /// // から行末までを注釈として扱うかどうか
+    pub fn with_line_comments(mut self, enabled: bool) -> Self {
+        self.allow_line_comments = enabled;
+        self
+    }
+
+    /// 空白だけを挟んで並んだ文字列を，1つの文字列として扱うかどうか
+    /// 'ab' 'cd' は abcd という1つの文字列になり，その範囲は両方の文字列を覆う
+    pub fn with_string_concatenation(mut self, enabled: bool) -> Self {
+        self.concatenate_strings = enabled;
+        self
+    }
+
+    /// 文字列の値を，シングルクォートを重ねた '' を1つにせず，書かれたとおりに持つかどうか
+    /// どちらの場合も，トークンの範囲は両端のシングルクォートを含む
+    pub fn with_raw_strings(mut self, enabled: bool) -> Self {
+        self.raw_strings = enabled;
+        self
+    }
+
+    /// 名前のトークンの値を，綴りの文字列ではなく TokenValue::Ident の番号にするかどうか
+    /// 番号の綴りは interner() で引く
+    pub fn with_interning(mut self, enabled: bool) -> Self {
+        self.interner = enabled.then(Interner::new);
+        self
+    }
+
+    /// with_interningを有効にしたときに，これまでに読んだ名前の表
+    pub fn interner(&self) -> Option<&Interner> {
+        self.interner.as_ref()
+    }
+
+    /// 予約語の表を変える
+    /// extraの綴りはその種類のキーワードになり，removedの綴りは名前として扱う
+    pub fn with_keywords(mut self, extra: &[(&str, Kind)], removed: &[&str]) -> Self {
+        self.extra_keywords
+            .extend(extra.iter().map(|(s, kind)| (s.to_string(), *kind)));
+        self.removed_keywords
+            .extend(removed.iter().map(|s| s.to_string()));
+        self
+    }
+
     pub fn analyze(&mut self) -> Vec<Token> {
         let mut token_vec = Vec::new();
         loop {
@@ -171,8 +343,14 @@ impl<'a> Lexer<'a> {
         token_vec
     }
 
+    /// 字句の誤りがあっても最後まで読み進め，トークン列と誤りの両方を返す
+    pub fn analyze_recover(&mut self) -> (Vec<Token>, Vec<LexError>) {
+        let tokens = self.analyze();
+        (tokens, std::mem::take(&mut self.errors))
+    }
+
     pub fn read_next_token(&mut self) -> Token {
-        while let Some(c) = self.chars.peek() {
+        while let Some(c) = self.peek() {
             // EBNFのprogramに該当
             match c {
                 // 分離子
@@ -187,7 +365,7 @@ impl<'a> Lexer<'a> {
                     // token()関数の呼び出し元（つまりこの関数）でchars.next()を呼び出すことで，
                     // unwrap()でpanicが起きる可能性を排除するコードの距離を短くしている
                     let c = self.chars.next().unwrap();
-                    let (kind, value) = self.token(c);
+                    let (kind, value) = self.token(c, start);
                     let end = self.offset();
 
                     return Token {
@@ -210,14 +388,17 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn offset(&self) -> usize {
-        // self.chars.clone().count()の計算量を調べた方がいいかもしれない
-        // self.source.len()は fat pointerによりO(1)だが，後者はO(n)の可能性あり
+    // Charsのクローンは位置を複製するだけなので，O(1)で次の文字を覗ける
+    fn peek(&self) -> Option<char> {
+        self.chars.clone().next()
+    }
 
-        // イテレータを消費し，Noneを返すまでの要素数を返す
-        // ので，count()の計算量はO(n)になると思う
-        // ややコストが高めかもしれない
-        self.source.len() - self.chars.clone().count()
+    fn offset(&self) -> usize {
+        // 以前は self.source.len() - self.chars.clone().count() としていたが，
+        // count()は残りの文字数を数えるのでO(n)かかり，字句解析全体がO(n^2)になっていた
+        // また，len()はバイト数，count()は文字数なので，マルチバイト文字があると位置がずれていた
+        // as_str()は残りの部分文字列をO(1)で返すので，バイト単位の位置を正しく求められる
+        self.source.len() - self.chars.as_str().len()
     }
 
     fn comment(&mut self, c: char) {
@@ -226,12 +407,25 @@ impl<'a> Lexer<'a> {
             '{' => {
                 self.comment_brace();
             }
+            // /の次が/なら行注釈，そうでなければ /* */ の注釈
+            '/' if self.allow_line_comments && self.peek() == Some('/') => {
+                self.comment_line();
+            }
             '/' => {
                 self.comment_slashstar();
             }
             _ => {}
         }
     }
+
+    fn comment_line(&mut self) {
+        for c in self.chars.by_ref() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
     fn comment_brace(&mut self) {
         for c in self.chars.by_ref() {
             if c == '}' {
@@ -270,20 +464,31 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn token(&mut self, c: char) -> (Kind, TokenValue) {
+    fn token(&mut self, c: char, start: usize) -> (Kind, TokenValue) {
         // EBNFのtoken，字句に該当
         match c {
             'a'..='z' | 'A'..='Z' => self.name_keyword(c),
-            '0'..='9' => self.unsigned_integer(c),
-            '\'' => self.string(),
-            _ => self.symbol(c),
+            '0'..='9' => self.unsigned_integer(c, start),
+            '\'' if self.concatenate_strings => self.adjacent_strings(start),
+            '\'' => self.string(start),
+            '!' if self.operator_aliases && self.peek() == Some('=') => self.symbol(c, start),
+            _ if starts_symbol(c) => self.symbol(c, start),
+            _ => self.unknown_character(c, start),
         }
     }
 
+    // どの字句にもならない文字は，その1文字だけで Unknown のトークンにする
+    // 後ろに続く記号を巻き込まないので，周りのトークンはそのまま読める
+    fn unknown_character(&mut self, c: char, start: usize) -> (Kind, TokenValue) {
+        let end = self.offset();
+        self.errors.push(LexError::UnknownCharacter { start, end });
+        (Kind::Unknown, TokenValue::String(String::from(c)))
+    }
+
     fn name_keyword(&mut self, c: char) -> (Kind, TokenValue) {
         let mut buf = String::from(c);
 
-        while let Some(c) = self.chars.peek() {
+        while let Some(c) = self.peek() {
             match c {
                 'a'..='z' | 'A'..='Z' | '0'..='9' => {
                     buf.push(self.chars.next().unwrap());
@@ -293,17 +498,31 @@ impl<'a> Lexer<'a> {
                 }
             }
         }
-        let kind = match_keyword(&buf);
+        let kind = self.keyword(&buf);
         match kind {
-            Kind::Name => (kind, TokenValue::String(buf)),
+            Kind::Name => match self.interner.as_mut() {
+                Some(interner) => (kind, TokenValue::Ident(interner.intern(&buf))),
+                None => (kind, TokenValue::String(buf)),
+            },
             _ => (kind, TokenValue::None),
         }
     }
 
-    fn unsigned_integer(&mut self, c: char) -> (Kind, TokenValue) {
+    // with_keywordsで変えた表を先に引き，なければ既定の予約語を引く
+    fn keyword(&self, ident: &str) -> Kind {
+        if self.removed_keywords.contains(ident) {
+            return Kind::Name;
+        }
+        match self.extra_keywords.get(ident) {
+            Some(kind) => *kind,
+            None => match_keyword(ident),
+        }
+    }
+
+    fn unsigned_integer(&mut self, c: char, start: usize) -> (Kind, TokenValue) {
         let mut buf = String::from(c);
 
-        while let Some(c) = self.chars.peek() {
+        while let Some(c) = self.peek() {
             match c {
                 '0'..='9' => {
                     buf.push(self.chars.next().unwrap());
@@ -313,32 +532,38 @@ impl<'a> Lexer<'a> {
                 }
             }
         }
-        (
-            Kind::UnsignedInteger,
-            TokenValue::Integer(buf.parse().unwrap()),
-        )
+        // u32にも収まらない場合は，値を最大値に丸めておく
+        let value = buf.parse().unwrap_or(u32::MAX);
+        if value > MAX_UNSIGNED_INTEGER {
+            let end = self.offset();
+            self.errors.push(LexError::IntegerOutOfRange { start, end });
+        }
+        (Kind::UnsignedInteger, TokenValue::Integer(value))
     }
 
-    fn string(&mut self) -> (Kind, TokenValue) {
+    fn string(&mut self, start: usize) -> (Kind, TokenValue) {
         enum State {
             SingleQuote,
             Other,
         }
         let mut state = State::Other;
         let mut buf = String::new();
-        while let Some(c) = self.chars.peek() {
+        while let Some(c) = self.peek() {
             match state {
                 State::Other => {
-                    if c == &'\'' {
+                    if c == '\'' {
                         state = State::SingleQuote;
                     }
                 }
                 State::SingleQuote => {
-                    if c == &'\'' {
+                    if c == '\'' {
                         state = State::Other;
                         // 文字列中のシングルクォートは，2つで1つのシングルクォートとして扱う
                         // そのため，ここで1つ目のシングルクォートを取り除く
-                        buf.pop();
+                        // 書かれたとおりの値を持つ場合は，取り除かない
+                        if !self.raw_strings {
+                            buf.pop();
+                        }
                     } else {
                         break;
                     }
@@ -347,6 +572,13 @@ impl<'a> Lexer<'a> {
             buf.push(self.chars.next().unwrap());
         }
 
+        // 閉じるシングルクォートを読んだ直後の状態で終わっていなければ，文字列が閉じていない
+        if let State::Other = state {
+            let end = self.offset();
+            self.errors
+                .push(LexError::UnterminatedString { start, end });
+        }
+
         // 最後尾がシングルクォートであれば，取り除く
         if buf.ends_with('\'') {
             buf.pop();
@@ -355,43 +587,65 @@ impl<'a> Lexer<'a> {
         (Kind::String, TokenValue::String(buf))
     }
 
-    fn symbol(&mut self, c: char) -> (Kind, TokenValue) {
-        let mut buf = String::from(c);
-
-        while let Some(c) = self.chars.peek() {
-            // 1文字目の段階で確定する記号があるので，その場合break
-            if SYMBOLS_LEN_1.contains(&buf.as_str()) {
+    // 文字列を読み，その後ろに空白だけを挟んで続く文字列があれば，値をつなげる
+    // 後ろに文字列が続かない場合，間の空白は読み進めずに残す
+    fn adjacent_strings(&mut self, start: usize) -> (Kind, TokenValue) {
+        let mut buf = String::new();
+        let mut start = start;
+        loop {
+            if let (_, TokenValue::String(s)) = self.string(start) {
+                buf.push_str(&s);
+            }
+            let mut rest = self.chars.clone();
+            let next = rest.find(|c| !matches!(c, ' ' | '\t' | '\n' | '\r'));
+            if next != Some('\'') {
                 break;
             }
-            let cc = String::from(*c);
-            if match_symbol(&cc) == Kind::Unknown {
+            self.chars = rest;
+            start = self.offset() - 1;
+        }
+        (Kind::String, TokenValue::String(buf))
+    }
+
+    // 記号は最長一致で読む．続く文字を足しても記号（または有効な別表記）であるうちだけ伸ばす
+    // >( や :=- のように記号が続く場合は，それぞれ別の記号になる
+    fn symbol(&mut self, c: char, start: usize) -> (Kind, TokenValue) {
+        let mut buf = String::from(c);
+
+        while let Some(c) = self.peek() {
+            let mut longer = buf.clone();
+            longer.push(c);
+            if self.symbol_kind(&longer) == Kind::Unknown {
                 break;
             }
-            // // 1文字目の段階で確定する記号があるので，その場合break
-            // ここにこの処理を置いていたとき，なぜかレキサーのテストケースは通過する
-            // パーサーのテストでは，:=を: と字句解析して異常を起こしたのに なぜ差があるのか不明
-            // if SYMBOLS_LEN_1.contains(&buf.as_str()) {
-            //     break;
-            // }
-            buf.push(self.chars.next().unwrap());
+            buf = longer;
+            self.chars.next();
         }
 
-        let kind = match_symbol(&buf);
+        let kind = self.symbol_kind(&buf);
         if kind != Kind::Unknown {
             (kind, TokenValue::None)
         } else {
+            // 別表記の1文字目だけで終わった場合など．Unknown のトークンには必ず誤りを記録する
+            let end = self.offset();
+            self.errors.push(LexError::UnknownCharacter { start, end });
             (kind, TokenValue::String(buf))
         }
     }
+
+    fn symbol_kind(&self, symbol: &str) -> Kind {
+        match match_symbol(symbol) {
+            Kind::Unknown if self.operator_aliases => match_operator_alias(symbol),
+            kind => kind,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_lexer() {
-        let source = "
+    const TEST_LEXER_SOURCE: &str = "
         {name}
         name1 name2name3
         {keyword}
@@ -403,7 +657,7 @@ mod tests {
         not or div and
         char integer boolean
         read write readln writeln
-        true false break
+        true false break forward
         {unsigned integer}
         0 1 9 255 
         {string}
@@ -413,7 +667,10 @@ mod tests {
         + - * = <> < <= > >=
         ( ) [ ] := . , : ;
         ";
-        let mut lexer = Lexer::new(source);
+
+    #[test]
+    fn test_lexer() {
+        let mut lexer = Lexer::new(TEST_LEXER_SOURCE);
         let tokens = lexer.analyze();
 
         let expected = vec![
@@ -447,6 +704,7 @@ mod tests {
             (Kind::True, TokenValue::None),
             (Kind::False, TokenValue::None),
             (Kind::Break, TokenValue::None),
+            (Kind::Forward, TokenValue::None),
             (Kind::UnsignedInteger, TokenValue::Integer(0)),
             (Kind::UnsignedInteger, TokenValue::Integer(1)),
             (Kind::UnsignedInteger, TokenValue::Integer(9)),
@@ -483,4 +741,467 @@ mod tests {
             assert_eq!(token.value, expected[i].1);
         }
     }
+
+    #[test]
+    fn test_lexer_positions() {
+        let tokens = Lexer::new(TEST_LEXER_SOURCE).analyze();
+        let spellings = [
+            "name1",
+            "name2name3",
+            "program",
+            "var",
+            "array",
+            "of",
+            "begin",
+            "end",
+            "if",
+            "then",
+            "else",
+            "procedure",
+            "return",
+            "call",
+            "while",
+            "do",
+            "not",
+            "or",
+            "div",
+            "and",
+            "char",
+            "integer",
+            "boolean",
+            "read",
+            "write",
+            "readln",
+            "writeln",
+            "true",
+            "false",
+            "break",
+            "forward",
+            "0",
+            "1",
+            "9",
+            "255",
+            "'string'",
+            "'string1''string2'",
+            "+",
+            "-",
+            "*",
+            "=",
+            "<>",
+            "<",
+            "<=",
+            ">",
+            ">=",
+            "(",
+            ")",
+            "[",
+            "]",
+            ":=",
+            ".",
+            ",",
+            ":",
+            ";",
+        ];
+        assert_eq!(tokens.len(), spellings.len() + 1);
+
+        // 各トークンは，前のトークンの後ろで最初に現れる綴りの位置にある
+        // {name} などの注釈の中の綴りは，注釈の後ろから探すので拾わない
+        let mut cursor = 0;
+        for (token, spelling) in tokens.iter().zip(spellings) {
+            let start = cursor + TEST_LEXER_SOURCE[cursor..].find(spelling).unwrap();
+            assert_eq!(
+                (token.start, token.end),
+                (start, start + spelling.len()),
+                "{:?}",
+                token
+            );
+            cursor = token.end;
+        }
+        let eof = tokens.last().unwrap();
+        assert_eq!(eof.kind, Kind::Eof);
+        assert_eq!(
+            (eof.start, eof.end),
+            (TEST_LEXER_SOURCE.len(), TEST_LEXER_SOURCE.len())
+        );
+
+        // 注釈の直後と，シングルクォートを重ねた文字列の位置
+        assert_eq!(
+            tokens[0].start,
+            TEST_LEXER_SOURCE.find("{name}").unwrap() + "{name}\n        ".len()
+        );
+        let doubled = tokens
+            .iter()
+            .find(|t| t.value == TokenValue::String("string1'string2".to_string()))
+            .unwrap();
+        assert_eq!(doubled.end - doubled.start, "'string1''string2'".len());
+    }
+
+    #[test]
+    fn test_multibyte_positions() {
+        // start/endは文字数ではなくバイト数で数える
+        let source = "{注釈} x := 'あい''う' + y";
+        let tokens = Lexer::new(source).analyze();
+        let spans = tokens.iter().map(|t| (t.start, t.end)).collect::<Vec<_>>();
+        let x = source.find('x').unwrap();
+        let string = source.find('\'').unwrap();
+        let y = source.find('y').unwrap();
+        assert_eq!(x, 9);
+        assert_eq!(
+            spans,
+            vec![
+                (x, x + 1),
+                (x + 2, x + 4),
+                (string, string + "'あい''う'".len()),
+                (y - 2, y - 1),
+                (y, y + 1),
+                (source.len(), source.len()),
+            ]
+        );
+        assert_eq!(tokens[2].value, TokenValue::String("あい'う".to_string()));
+    }
+
+    #[test]
+    fn test_canonical_dump() {
+        let tokens = Lexer::new(TEST_LEXER_SOURCE).analyze();
+        let golden = "\
+            3:9-3:14  Name  \"name1\"\n\
+            3:15-3:25  Name  \"name2name3\"\n\
+            5:9-5:16  Program\n\
+            5:17-5:20  Var\n\
+            5:21-5:26  Array\n\
+            6:9-6:11  Of\n\
+            6:12-6:17  Begin\n\
+            6:18-6:21  End\n\
+            7:9-7:11  If\n\
+            7:12-7:16  Then\n\
+            7:17-7:21  Else\n\
+            8:9-8:18  Procedure\n\
+            8:19-8:25  Return\n\
+            9:9-9:13  Call\n\
+            9:14-9:19  While\n\
+            9:20-9:22  DO\n\
+            10:9-10:12  Not\n\
+            10:13-10:15  Or\n\
+            10:16-10:19  Div\n\
+            10:20-10:23  And\n\
+            11:9-11:13  Char\n\
+            11:14-11:21  Integer\n\
+            11:22-11:29  Boolean\n\
+            12:9-12:13  Read\n\
+            12:14-12:19  Write\n\
+            12:20-12:26  Readln\n\
+            12:27-12:34  Writeln\n\
+            13:9-13:13  True\n\
+            13:14-13:19  False\n\
+            13:20-13:25  Break\n\
+            13:26-13:33  Forward\n\
+            15:9-15:10  UnsignedInteger  0\n\
+            15:11-15:12  UnsignedInteger  1\n\
+            15:13-15:14  UnsignedInteger  9\n\
+            15:15-15:18  UnsignedInteger  255\n\
+            17:9-17:17  String  \"string\"\n\
+            18:9-18:27  String  \"string1'string2\"\n\
+            20:9-20:10  Plus\n\
+            20:11-20:12  Minus\n\
+            20:13-20:14  Star\n\
+            20:15-20:16  Equal\n\
+            20:17-20:19  NotEq\n\
+            20:20-20:21  Less\n\
+            20:22-20:24  LessEq\n\
+            20:25-20:26  Great\n\
+            20:27-20:29  GreatEq\n\
+            21:9-21:10  LParen\n\
+            21:11-21:12  RParen\n\
+            21:13-21:14  LBracket\n\
+            21:15-21:16  RBracket\n\
+            21:17-21:19  Assign\n\
+            21:20-21:21  Dot\n\
+            21:22-21:23  Comma\n\
+            21:24-21:25  Colon\n\
+            21:26-21:27  Semicolon\n\
+            22:9-22:9  Eof\n";
+        assert_eq!(canonical_dump(TEST_LEXER_SOURCE, &tokens), golden);
+
+        // 位置順に並べるので，トークン列の順序には依らない
+        let mut reversed = tokens.clone();
+        reversed.reverse();
+        assert_eq!(canonical_dump(TEST_LEXER_SOURCE, &reversed), golden);
+    }
+
+    #[test]
+    fn test_lint_lexical() {
+        let source = "program p; begin x := 99999; writeln('abc) end.";
+        let errors = lint_lexical(source);
+
+        let int = source.find("99999").unwrap();
+        let string = source.find('\'').unwrap();
+        assert_eq!(
+            errors,
+            vec![
+                LexError::IntegerOutOfRange {
+                    start: int,
+                    end: int + 5
+                },
+                LexError::UnterminatedString {
+                    start: string,
+                    end: source.len()
+                },
+            ]
+        );
+
+        assert_eq!(lint_lexical("program p; begin x := 32767 end."), vec![]);
+        assert_eq!(lint_lexical("'it''s' ''''"), vec![]);
+    }
+
+    #[test]
+    fn test_adjacent_symbols() {
+        let kinds = |source: &str| {
+            Lexer::new(source)
+                .analyze()
+                .iter()
+                .map(|t| t.kind)
+                .collect::<Vec<_>>()
+        };
+        // 空白なしで続く記号は，それぞれ別の記号として読む
+        for source in ["x :=-1", "a>(b)", "a<-1", "a[i]:=-b"] {
+            assert_eq!(lint_lexical(source), vec![], "{}", source);
+        }
+        assert_eq!(kinds("x :=-1"), kinds("x := -1"));
+        assert_eq!(
+            kinds("a>(b)"),
+            vec![
+                Kind::Name,
+                Kind::Great,
+                Kind::LParen,
+                Kind::Name,
+                Kind::RParen,
+                Kind::Eof
+            ]
+        );
+        assert_eq!(
+            kinds("a<-1"),
+            vec![
+                Kind::Name,
+                Kind::Less,
+                Kind::Minus,
+                Kind::UnsignedInteger,
+                Kind::Eof
+            ]
+        );
+        assert_eq!(kinds("a[i]:=-b"), kinds("a [ i ] := - b"));
+        // 2文字の記号はそのまま1つに読む
+        assert_eq!(kinds("<><=>=:="), kinds("<> <= >= :="));
+    }
+
+    #[test]
+    fn test_analyze_recover() {
+        let (tokens, errors) = Lexer::new("x := 40000 + 1").analyze_recover();
+        assert_eq!(tokens.len(), 6);
+        assert_eq!(tokens[2].value, TokenValue::Integer(40000));
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_unknown_character() {
+        for c in ["@", "#", "!"] {
+            let source = format!("x := {}(y)", c);
+            let (tokens, errors) = Lexer::new(&source).analyze_recover();
+            let start = source.find(c).unwrap();
+            assert_eq!(
+                errors,
+                vec![LexError::UnknownCharacter {
+                    start,
+                    end: start + 1
+                }]
+            );
+
+            let kinds = tokens.iter().map(|t| t.kind).collect::<Vec<_>>();
+            assert_eq!(
+                kinds,
+                vec![
+                    Kind::Name,
+                    Kind::Assign,
+                    Kind::Unknown,
+                    Kind::LParen,
+                    Kind::Name,
+                    Kind::RParen,
+                    Kind::Eof,
+                ]
+            );
+            assert_eq!(tokens[2].value, TokenValue::String(c.to_string()));
+            assert_eq!((tokens[3].start, tokens[3].end), (start + 1, start + 2));
+        }
+
+        // 別表記を受け付けても，= が続かない ! は不明な文字
+        let source = "a ! b";
+        let (tokens, errors) = Lexer::new(source)
+            .with_operator_aliases(true)
+            .analyze_recover();
+        assert_eq!(
+            errors,
+            vec![LexError::UnknownCharacter { start: 2, end: 3 }]
+        );
+        assert_eq!(tokens[1].value, TokenValue::String("!".to_string()));
+    }
+
+    #[test]
+    fn test_with_keywords() {
+        let source = "repeat break until x";
+        let kinds = |mut lexer: Lexer| lexer.analyze().iter().map(|t| t.kind).collect::<Vec<_>>();
+
+        assert_eq!(
+            kinds(Lexer::new(source)),
+            vec![Kind::Name, Kind::Break, Kind::Name, Kind::Name, Kind::Eof]
+        );
+        let custom = Lexer::new(source).with_keywords(
+            &[("repeat", Kind::Repeat), ("until", Kind::Until)],
+            &["break"],
+        );
+        assert_eq!(
+            kinds(custom),
+            vec![Kind::Repeat, Kind::Name, Kind::Until, Kind::Name, Kind::Eof]
+        );
+
+        // 外したキーワードは，名前としての綴りを値に持つ
+        let tokens = Lexer::new("break").with_keywords(&[], &["break"]).analyze();
+        assert_eq!(tokens[0].value, TokenValue::String("break".to_string()));
+    }
+
+    #[test]
+    fn test_string_concatenation() {
+        let tokens = Lexer::new("'ab' 'cd'").analyze();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!((tokens[1].start, tokens[1].end), (5, 9));
+        let tokens = Lexer::new("'ab' 'cd'")
+            .with_string_concatenation(true)
+            .analyze();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].value, TokenValue::String("abcd".to_string()));
+        assert_eq!((tokens[0].start, tokens[0].end), (0, 9));
+
+        let source = "x 'ab' 'c''d'\n\t'e' y 'f'";
+        let tokens = Lexer::new(source).analyze();
+        assert_eq!(tokens.len(), 7);
+
+        let tokens = Lexer::new(source).with_string_concatenation(true).analyze();
+        let values = tokens
+            .iter()
+            .map(|t| (t.kind, t.value.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                (Kind::Name, TokenValue::String("x".to_string())),
+                (Kind::String, TokenValue::String("abc'de".to_string())),
+                (Kind::Name, TokenValue::String("y".to_string())),
+                (Kind::String, TokenValue::String("f".to_string())),
+                (Kind::Eof, TokenValue::None),
+            ]
+        );
+        let start = source.find("'ab'").unwrap();
+        let end = source.find("'e'").unwrap() + 3;
+        assert_eq!((tokens[1].start, tokens[1].end), (start, end));
+        // 文字列の後ろの空白は，次のトークンの位置に影響しない
+        assert_eq!(tokens[2].start, source.find('y').unwrap());
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        for (source, unescaped, raw) in [("'a''b'", "a'b", "a''b"), ("''''", "'", "''")] {
+            let tokens = Lexer::new(source).analyze();
+            assert_eq!(tokens[0].value, TokenValue::String(unescaped.to_string()));
+            assert_eq!((tokens[0].start, tokens[0].end), (0, source.len()));
+
+            let tokens = Lexer::new(source).with_raw_strings(true).analyze();
+            assert_eq!(tokens[0].value, TokenValue::String(raw.to_string()));
+            assert_eq!((tokens[0].start, tokens[0].end), (0, source.len()));
+        }
+    }
+
+    #[test]
+    fn test_token_at() {
+        let source = "x:=10 {c} y";
+        let tokens = Lexer::new(source).analyze();
+        let kind_at = |offset| token_at(&tokens, offset).map(|t| t.kind);
+
+        assert_eq!(kind_at(0), Some(Kind::Name));
+        // := と 10 の内側と先頭
+        assert_eq!(kind_at(1), Some(Kind::Assign));
+        assert_eq!(kind_at(2), Some(Kind::Assign));
+        assert_eq!(kind_at(4), Some(Kind::UnsignedInteger));
+        // トークンの間の空白と注釈
+        assert_eq!(kind_at(5), None);
+        assert_eq!(kind_at(7), None);
+        assert_eq!(kind_at(10), Some(Kind::Name));
+        assert_eq!(kind_at(source.len()), None);
+    }
+
+    #[test]
+    fn test_line_comments() {
+        let source = "// line comment\n name";
+        let tokens = Lexer::new(source).with_line_comments(true).analyze();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].kind, Kind::Name);
+        assert_eq!(tokens[0].value, TokenValue::String("name".to_string()));
+        assert_eq!(tokens[0].start, source.find("name").unwrap());
+        assert_eq!(tokens[1].kind, Kind::Eof);
+
+        // 行注釈の中の /* や，/* */ の中の // は，それぞれの注釈の一部
+        let source = "// /* not a block\n a /* // not a line */ b";
+        let tokens = Lexer::new(source).with_line_comments(true).analyze();
+        let names = tokens.iter().map(|t| t.value.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                TokenValue::String("a".to_string()),
+                TokenValue::String("b".to_string()),
+                TokenValue::None,
+            ]
+        );
+
+        // 既定では // は行注釈にならない
+        let tokens = Lexer::new("// line\n name */ x").analyze();
+        assert_eq!(tokens[0].value, TokenValue::String("x".to_string()));
+    }
+
+    #[test]
+    fn test_operator_aliases() {
+        let kinds = |mut lexer: Lexer| {
+            lexer
+                .analyze()
+                .iter()
+                .map(|t| (t.kind, t.value.clone()))
+                .collect::<Vec<_>>()
+        };
+        let canonical = kinds(Lexer::new("a <> b"));
+        assert_eq!(
+            kinds(Lexer::new("a != b").with_operator_aliases(true)),
+            canonical
+        );
+        assert_eq!(canonical[1], (Kind::NotEq, TokenValue::None));
+
+        // 既定では別表記は受け付けず，! は不明な文字になる
+        let (tokens, errors) = Lexer::new("a != b").analyze_recover();
+        assert_eq!(
+            errors,
+            vec![LexError::UnknownCharacter { start: 2, end: 3 }]
+        );
+        assert_eq!(tokens[1].kind, Kind::Unknown);
+        assert_eq!(tokens[1].value, TokenValue::String("!".to_string()));
+        assert_eq!(tokens[2].kind, Kind::Equal);
+
+        // 別表記の後ろに記号が続いても，後ろの記号を巻き込まない
+        assert_eq!(
+            kinds(Lexer::new("a!==b").with_operator_aliases(true)),
+            kinds(Lexer::new("a <> = b"))
+        );
+        assert_eq!(
+            kinds(Lexer::new("a!=(b)").with_operator_aliases(true)),
+            kinds(Lexer::new("a <> ( b )"))
+        );
+        for source in ["a>(b)", "x:=-1", "a!=-1"] {
+            let mut lexer = Lexer::new(source).with_operator_aliases(true);
+            assert_eq!(lexer.analyze_recover().1, vec![], "{}", source);
+        }
+    }
 }
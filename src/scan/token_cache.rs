@@ -0,0 +1,307 @@
+// 字句解析の結果を，小さなバイナリ形式で保存・復元する
+// 遅い後段のツールを何度も動かすときに，字句解析をやり直さずに済ませるためのもの
+//
+// 形式（数値はすべてリトルエンディアン）
+//   "MPLT" バージョン(u8) ソースコードのハッシュ(u64) トークン数(u32)
+//   トークンごとに 種類(u8) start(u32) end(u32) 値
+//   値は 0: なし / 1: 整数(u32) / 2: 文字列 長さ(u32) UTF-8のバイト列 / 3: 名前の番号(u32)
+//   名前の番号の綴りを引くInternerは保存しないので，使う側で別に保存しておく
+
+use std::io::{self, Read, Write};
+
+use super::interner::Symbol;
+use super::scan3::{Kind, Token, TokenValue};
+
+const MAGIC: &[u8; 4] = b"MPLT";
+// Kindの並びや値の種類を変えたら上げる
+// 2: Forward, Repeat, Until と 名前の番号(3) を追加
+const VERSION: u8 = 2;
+
+// Kindの宣言順に並べたもの
+// Kindを u8 にするときは `as u8` を使い，戻すときはこの配列を引く
+const KINDS: &[Kind] = &[
+    Kind::Eof,
+    Kind::Name,
+    Kind::UnsignedInteger,
+    Kind::String,
+    Kind::Program,
+    Kind::Var,
+    Kind::Array,
+    Kind::Of,
+    Kind::Begin,
+    Kind::End,
+    Kind::If,
+    Kind::Then,
+    Kind::Else,
+    Kind::Procedure,
+    Kind::Return,
+    Kind::Call,
+    Kind::While,
+    Kind::DO,
+    Kind::Not,
+    Kind::Or,
+    Kind::Div,
+    Kind::And,
+    Kind::Char,
+    Kind::Integer,
+    Kind::Boolean,
+    Kind::Read,
+    Kind::Write,
+    Kind::Readln,
+    Kind::Writeln,
+    Kind::True,
+    Kind::False,
+    Kind::Break,
+    Kind::Forward,
+    Kind::Repeat,
+    Kind::Until,
+    Kind::Plus,
+    Kind::Minus,
+    Kind::Star,
+    Kind::Equal,
+    Kind::NotEq,
+    Kind::Less,
+    Kind::LessEq,
+    Kind::Great,
+    Kind::GreatEq,
+    Kind::LParen,
+    Kind::RParen,
+    Kind::LBracket,
+    Kind::RBracket,
+    Kind::Assign,
+    Kind::Dot,
+    Kind::Comma,
+    Kind::Colon,
+    Kind::Semicolon,
+    Kind::Unknown,
+];
+
+// 保存したトークン列を復元できなかった理由
+#[derive(Debug)]
+pub enum TokenCacheError {
+    Io(io::Error),
+    // 先頭の識別子やバージョン，トークンの中身が形式に合わない
+    BadFormat,
+    // 保存したときとソースコードが変わっている
+    StaleSource { expected: u64, found: u64 },
+}
+
+impl std::fmt::Display for TokenCacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TokenCacheError::Io(e) => write!(f, "token cache io error: {}", e),
+            TokenCacheError::BadFormat => write!(f, "token cache is not in the expected format"),
+            TokenCacheError::StaleSource { expected, found } => write!(
+                f,
+                "token cache is stale: source hash {:016x} but cache was made from {:016x}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TokenCacheError {}
+
+impl From<io::Error> for TokenCacheError {
+    fn from(e: io::Error) -> Self {
+        TokenCacheError::Io(e)
+    }
+}
+
+/// ソースコードのハッシュ (FNV-1a 64bit)
+/// 実行するたびに値が変わると保存した結果を使えないので，標準のHasherではなく固定の計算方法を使う
+pub fn source_hash(source: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in source.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// sourceを字句解析したtokensを，sourceのハッシュとともに書き出す
+pub fn serialize_tokens(source: &str, tokens: &[Token], w: &mut impl Write) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION])?;
+    w.write_all(&source_hash(source).to_le_bytes())?;
+    write_u32(w, tokens.len())?;
+    for token in tokens {
+        w.write_all(&[token.kind as u8])?;
+        write_u32(w, token.start)?;
+        write_u32(w, token.end)?;
+        match &token.value {
+            TokenValue::None => w.write_all(&[0])?,
+            TokenValue::Integer(n) => {
+                w.write_all(&[1])?;
+                w.write_all(&n.to_le_bytes())?;
+            }
+            TokenValue::String(s) => {
+                w.write_all(&[2])?;
+                write_u32(w, s.len())?;
+                w.write_all(s.as_bytes())?;
+            }
+            TokenValue::Ident(symbol) => {
+                w.write_all(&[3])?;
+                w.write_all(&symbol.0.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// serialize_tokensで書き出したトークン列を読み込む
+/// 保存したときのソースコードとsourceが異なる場合は StaleSource を返す
+pub fn deserialize_tokens(source: &str, r: &mut impl Read) -> Result<Vec<Token>, TokenCacheError> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u8(r)? != VERSION {
+        return Err(TokenCacheError::BadFormat);
+    }
+    let mut hash = [0; 8];
+    r.read_exact(&mut hash)?;
+    let found = u64::from_le_bytes(hash);
+    let expected = source_hash(source);
+    if found != expected {
+        return Err(TokenCacheError::StaleSource { expected, found });
+    }
+
+    // 保存された数はそのまま信用せず，ソースコードから作りうる数までしか先に確保しない
+    // トークンは1バイト以上あるので，Eofを含めてもソースコードのバイト数+1を超えない
+    let len = read_u32(r)? as usize;
+    let mut tokens = Vec::with_capacity(len.min(source.len() + 1));
+    for _ in 0..len {
+        let kind = *KINDS
+            .get(read_u8(r)? as usize)
+            .ok_or(TokenCacheError::BadFormat)?;
+        let start = read_u32(r)? as usize;
+        let end = read_u32(r)? as usize;
+        let value = match read_u8(r)? {
+            0 => TokenValue::None,
+            1 => TokenValue::Integer(read_u32(r)?),
+            2 => {
+                // 長さの分だけ先に確保せず，実際に読めた分だけを受け取る
+                let len = read_u32(r)? as usize;
+                let mut buf = vec![];
+                r.by_ref().take(len as u64).read_to_end(&mut buf)?;
+                if buf.len() != len {
+                    return Err(TokenCacheError::BadFormat);
+                }
+                TokenValue::String(String::from_utf8(buf).map_err(|_| TokenCacheError::BadFormat)?)
+            }
+            3 => TokenValue::Ident(Symbol(read_u32(r)?)),
+            _ => return Err(TokenCacheError::BadFormat),
+        };
+        tokens.push(Token {
+            kind,
+            start,
+            end,
+            value,
+        });
+    }
+    Ok(tokens)
+}
+
+fn write_u32(w: &mut impl Write, n: usize) -> io::Result<()> {
+    let n = u32::try_from(n).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    w.write_all(&n.to_le_bytes())
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::scan3::Lexer;
+
+    #[test]
+    fn test_kinds_are_in_declaration_order() {
+        for (i, kind) in KINDS.iter().enumerate() {
+            assert_eq!(*kind as usize, i, "{:?}", kind);
+        }
+        assert_eq!(*KINDS.last().unwrap(), Kind::Unknown);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let source = "program p; var x : integer; begin x := 255; writeln('it''s', x) end.";
+        let tokens = Lexer::new(source).analyze();
+
+        let mut buf = vec![];
+        serialize_tokens(source, &tokens, &mut buf).unwrap();
+        let loaded = deserialize_tokens(source, &mut buf.as_slice()).unwrap();
+        assert_eq!(loaded, tokens);
+
+        // 名前の番号もそのまま復元される
+        let tokens = Lexer::new(source).with_interning(true).analyze();
+        let mut buf = vec![];
+        serialize_tokens(source, &tokens, &mut buf).unwrap();
+        let loaded = deserialize_tokens(source, &mut buf.as_slice()).unwrap();
+        assert_eq!(loaded, tokens);
+    }
+
+    #[test]
+    fn test_stale_source() {
+        let source = "program p; begin end.";
+        let tokens = Lexer::new(source).analyze();
+        let mut buf = vec![];
+        serialize_tokens(source, &tokens, &mut buf).unwrap();
+
+        let changed = "program q; begin end.";
+        match deserialize_tokens(changed, &mut buf.as_slice()) {
+            Err(TokenCacheError::StaleSource { expected, found }) => {
+                assert_eq!(expected, source_hash(changed));
+                assert_eq!(found, source_hash(source));
+            }
+            other => panic!("expected StaleSource, got {:?}", other),
+        }
+
+        buf[4] = 1;
+        assert!(matches!(
+            deserialize_tokens(source, &mut buf.as_slice()),
+            Err(TokenCacheError::BadFormat)
+        ));
+
+        buf[0] = b'X';
+        assert!(matches!(
+            deserialize_tokens(source, &mut buf.as_slice()),
+            Err(TokenCacheError::BadFormat)
+        ));
+    }
+    #[test]
+    fn test_corrupt_lengths() {
+        let source = "program p; begin writeln('hi') end.";
+        let tokens = Lexer::new(source).analyze();
+        let mut buf = vec![];
+        serialize_tokens(source, &tokens, &mut buf).unwrap();
+
+        // トークン数が壊れていても，巨大な領域を確保せずに読み込みに失敗する
+        let mut count = buf.clone();
+        count[13..17].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            deserialize_tokens(source, &mut count.as_slice()),
+            Err(TokenCacheError::Io(_))
+        ));
+
+        // 文字列の長さが壊れている場合も同じ
+        let string = tokens.iter().position(|t| t.kind == Kind::String).unwrap();
+        let mut buf = vec![];
+        serialize_tokens(source, &tokens[string..string + 1], &mut buf).unwrap();
+        let offset = 17 + 10;
+        assert_eq!(buf[offset - 1], 2);
+        buf[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            deserialize_tokens(source, &mut buf.as_slice()),
+            Err(TokenCacheError::BadFormat)
+        ));
+    }
+}